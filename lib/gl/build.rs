@@ -14,7 +14,11 @@ fn main() {
         (4, 5),
         Profile::Core,
         Fallbacks::All,
-        ["GL_EXT_texture_filter_anisotropic"],
+        [
+            "GL_EXT_texture_filter_anisotropic",
+            "GL_EXT_texture_compression_s3tc",
+            "GL_EXT_texture_sRGB",
+        ],
     )
     .write_bindings(StructGenerator, &mut file)
     .unwrap();
@@ -1,75 +1,227 @@
 use crate::cube::{Line2D, Ray};
-use std::cell::RefCell;
-use std::rc::Rc;
-
-#[derive(Copy, Clone)]
-pub enum CameraMovement {
-    NEGATIVE,
-    POSITIVE,
-    NONE,
+use crate::double_buffer::Interpolatable;
+use crate::viewport::Viewport;
+
+pub fn world_up() -> glm::Vec3 {
+    glm::vec3(0., 1., 0.)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Projection {
+    Perspective {
+        fov_y: f32, // radians
+    },
+    Orthographic {
+        half_height: f32, // world units, smaller is more zoomed in
+    },
+    // Asymmetric frustum, extents measured on the near plane
+    OffAxis {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+    },
+}
+
+impl Projection {
+    pub fn perspective_degrees(fov_y: f32) -> Self {
+        Projection::Perspective {
+            fov_y: fov_y.to_radians(),
+        }
+    }
+
+    // Reverse-Z maps near to 1 and far to 0 (with 0..1 clip control), perspective far is infinite
+    pub fn matrix(&self, aspect: f32, near: f32, far: f32, reverse_z: bool) -> glm::Mat4 {
+        if reverse_z {
+            return self.reversed_matrix(aspect, near, far);
+        }
+
+        match *self {
+            Projection::Perspective { fov_y } => glm::perspective(aspect, fov_y, near, far),
+            Projection::Orthographic { half_height } => {
+                let half_width = half_height * aspect;
+                glm::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+            Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+            } => glm::mat4(
+                2. * near / (right - left),
+                0.,
+                (right + left) / (right - left),
+                0., //
+                0.,
+                2. * near / (top - bottom),
+                (top + bottom) / (top - bottom),
+                0., //
+                0.,
+                0.,
+                -(far + near) / (far - near),
+                -2. * far * near / (far - near), //
+                0.,
+                0.,
+                -1.,
+                0., //
+            ),
+        }
+    }
+
+    fn reversed_matrix(&self, aspect: f32, near: f32, far: f32) -> glm::Mat4 {
+        match *self {
+            Projection::Perspective { fov_y } => {
+                let f = 1. / (fov_y * 0.5).tan();
+
+                glm::mat4(
+                    f / aspect,
+                    0.,
+                    0.,
+                    0., //
+                    0.,
+                    f,
+                    0.,
+                    0., //
+                    0.,
+                    0.,
+                    0.,
+                    near, //
+                    0.,
+                    0.,
+                    -1.,
+                    0., //
+                )
+            }
+            Projection::Orthographic { half_height } => {
+                let half_width = half_height * aspect;
+
+                glm::mat4(
+                    1. / half_width,
+                    0.,
+                    0.,
+                    0., //
+                    0.,
+                    1. / half_height,
+                    0.,
+                    0., //
+                    0.,
+                    0.,
+                    1. / (far - near),
+                    far / (far - near), //
+                    0.,
+                    0.,
+                    0.,
+                    1., //
+                )
+            }
+            Projection::OffAxis {
+                left,
+                right,
+                bottom,
+                top,
+            } => glm::mat4(
+                2. * near / (right - left),
+                0.,
+                (right + left) / (right - left),
+                0., //
+                0.,
+                2. * near / (top - bottom),
+                (top + bottom) / (top - bottom),
+                0., //
+                0.,
+                0.,
+                0.,
+                near, //
+                0.,
+                0.,
+                -1.,
+                0., //
+            ),
+        }
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        match self {
+            Projection::Orthographic { .. } => true,
+            _ => false,
+        }
+    }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EditorView {
+    Top,
+    Front,
+    Side,
+}
+
+impl EditorView {
+    pub fn direction(&self) -> glm::Vec3 {
+        match self {
+            EditorView::Top => glm::vec3(0., -1., 0.),
+            EditorView::Front => glm::vec3(0., 0., -1.),
+            EditorView::Side => glm::vec3(-1., 0., 0.),
+        }
+    }
+}
+
+// Pose and projection only, input is handled by `CameraController`s
+#[derive(Clone)]
 pub struct Camera {
     pub screen_width: u32,
     pub screen_height: u32,
 
-    near_plane: f32,
-    far_plane: f32,
+    pub near_plane: f32,
+    pub far_plane: f32,
+    pub projection_mode: Projection,
+    pub reverse_z: bool, // needs 0..1 clip control, float depth and GREATER depth test
+    cursor_scale: f32,   // drawable pixels per window point (HiDPI)
+    viewport_origin: glm::TVec2<i32>, // bottom left of the viewport in the window
+    window_height: u32,
 
     pub projection: glm::Mat4,
     pub view: glm::Mat4,
 
     pub position: glm::Vec3,
     pub direction_to_camera: glm::Vec3, // normalized
-
-    // movement
-    speed: f32,
-    pub move_x: CameraMovement,
-    pub move_z: CameraMovement,
-    pub move_y: CameraMovement,
-
-    // rotating
-    last_cursor: glm::TVec2<i32>,
-    is_looking_around: bool,
-    cam_sensitive: f32,
-    yaw: f32,   // y
-    pitch: f32, // x
 }
 
 impl Default for Camera {
     fn default() -> Self {
-        let camera_up: glm::Vec3 = glm::vec3(0., 1., 0.);
-
         let position = glm::vec3(0., 4., 6.);
         let direction_to_camera = glm::vec3(0., -4., -6.).normalize(); // from 0,0,0 to camera
         let screen_width = 1024;
         let screen_height = 600;
         let near_plane = 0.1;
         let far_plane = 300.0;
+        let projection_mode = Projection::perspective_degrees(45.0);
 
         Self {
             screen_width,
             screen_height,
             near_plane,
             far_plane,
-            projection: glm::perspective(
+            projection_mode,
+            reverse_z: false,
+            cursor_scale: 1.,
+            viewport_origin: glm::vec2(0, 0),
+            window_height: screen_height,
+            projection: projection_mode.matrix(
                 screen_width as f32 / screen_height as f32,
-                45.0,
                 near_plane,
                 far_plane,
+                false,
             ),
-            view: glm::look_at(&position, &(&position + &direction_to_camera), &camera_up),
+            view: glm::look_at(&position, &(&position + &direction_to_camera), &world_up()),
             position,
             direction_to_camera,
-            speed: 0.1,
-            move_x: CameraMovement::NONE,
-            move_z: CameraMovement::NONE,
-            move_y: CameraMovement::NONE,
-            last_cursor: glm::vec2(0, 0),
-            is_looking_around: false,
-            cam_sensitive: 0.1,
-            yaw: 0.0,
-            pitch: 0.0,
         }
     }
 }
@@ -81,103 +233,203 @@ impl Camera {
         }
     }
 
-    pub fn update(&mut self) {
-        let camera_up: glm::Vec3 = glm::vec3(0., 1., 0.);
-
-        fn dir_val(m: CameraMovement) -> f32 {
-            match m {
-                CameraMovement::NEGATIVE => -1.,
-                CameraMovement::POSITIVE => 1.,
-                CameraMovement::NONE => 0.,
-            }
-        }
+    pub fn update_projection(&mut self) {
+        self.projection = self.projection_mode.matrix(
+            self.screen_width as f32 / self.screen_height.max(1) as f32,
+            self.near_plane,
+            self.far_plane,
+            self.reverse_z,
+        );
+    }
 
-        let move_x = dir_val(self.move_x);
-        let move_z = dir_val(self.move_z);
-        let move_y = dir_val(self.move_y);
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+        self.update_projection();
+    }
 
-        self.position += glm::normalize(&glm::cross(&self.direction_to_camera, &camera_up))
-            * self.speed
-            * move_x;
-        self.position += &self.direction_to_camera * self.speed * move_z;
-        self.position += camera_up * self.speed * move_y;
+    // Window depth of the near plane and a depth further away that is never infinite
+    pub fn depth_range(&self) -> (f32, f32) {
+        if self.reverse_z {
+            (1., 0.5)
+        } else {
+            (0., 1.)
+        }
+    }
 
-        self.view = glm::look_at(
-            &self.position,
-            &(&self.position + &self.direction_to_camera),
-            &camera_up,
-        );
+    pub fn clear_depth(&self) -> f64 {
+        if self.reverse_z {
+            0.
+        } else {
+            1.
+        }
     }
 
-    pub fn click(&mut self, x: i32, y: i32) {
-        self.last_cursor = glm::vec2(x, y);
-        self.is_looking_around = true;
+    pub fn depth_func(&self) -> gl::types::GLenum {
+        if self.reverse_z {
+            gl::GREATER
+        } else {
+            gl::LESS
+        }
     }
 
-    pub fn unclick(&mut self) {
-        self.is_looking_around = false;
+    pub fn set_projection(&mut self, projection_mode: Projection) {
+        self.projection_mode = projection_mode;
+        self.update_projection();
     }
 
-    pub fn handle_mouse(&mut self, x: i32, y: i32) {
-        if self.is_looking_around {
-            let x_offset = x - self.last_cursor.x;
-            let y_offset = self.last_cursor.y - y;
+    // Orthographic with the same visible size as perspective at `distance`, and back
+    pub fn toggle_orthographic(&mut self, distance: f32) {
+        let (tan_half_fov, _) = self.tan_half_fov();
 
-            self.last_cursor = glm::vec2(x, y);
+        match self.projection_mode {
+            Projection::Orthographic { half_height } => {
+                self.set_projection(Projection::Perspective {
+                    fov_y: 2. * (half_height / distance.max(self.near_plane)).atan(),
+                })
+            }
+            _ => self.set_projection(Projection::Orthographic {
+                half_height: distance * tan_half_fov,
+            }),
+        }
+    }
 
-            self.yaw += self.cam_sensitive * x_offset as f32;
-            self.pitch += self.cam_sensitive * y_offset as f32;
+    // Orthographic look along an axis at `target`
+    pub fn set_editor_view(&mut self, view: EditorView, target: &glm::Vec3, distance: f32) {
+        if !self.projection_mode.is_orthographic() {
+            self.toggle_orthographic(distance);
+        }
 
-            if self.pitch.abs() > 89.0 {
-                self.pitch = self.pitch.signum() * 89.0;
+        self.direction_to_camera = view.direction();
+        self.position = target - &self.direction_to_camera * distance;
+        self.update_view();
+    }
+
+    // Size of a screen pixel in world units, at `distance` from the camera
+    pub fn pixel_size(&self, distance: f32) -> f32 {
+        match self.projection_mode {
+            Projection::Orthographic { half_height } => {
+                2. * half_height / self.screen_height as f32
             }
+            _ => 2. * distance * self.tan_half_fov().0 / self.screen_height as f32,
+        }
+    }
 
-            let direction = glm::vec3(
-                self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
-                self.pitch.to_radians().sin(),
-                self.yaw.to_radians().sin() * self.pitch.to_radians().cos(),
-            );
+    // Size in drawable pixels, cursor positions are scaled from window points to them
+    pub fn resize(&mut self, width: u32, height: u32, cursor_scale: f32) {
+        self.screen_width = width.max(1);
+        self.screen_height = height.max(1);
+        self.cursor_scale = cursor_scale;
+        self.viewport_origin = glm::vec2(0, 0);
+        self.window_height = self.screen_height;
+        self.update_projection();
+    }
 
-            self.direction_to_camera = direction.normalize();
+    // Render into part of the window, screen coordinates become relative to the viewport
+    pub fn set_viewport(&mut self, viewport: &Viewport, window_height: u32) {
+        self.screen_width = viewport.width;
+        self.screen_height = viewport.height;
+        self.viewport_origin = glm::vec2(viewport.x, viewport.y);
+        self.window_height = window_height;
+        self.update_projection();
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        Viewport::new(
+            self.viewport_origin.x,
+            self.viewport_origin.y,
+            self.screen_width,
+            self.screen_height,
+        )
+    }
+
+    // world up, unless looking straight up or down (top view)
+    fn up(&self) -> glm::Vec3 {
+        if glm::cross(&self.direction_to_camera, &world_up()).magnitude() < 0.001 {
+            glm::vec3(0., 0., -self.direction_to_camera.y.signum())
+        } else {
+            world_up()
         }
     }
 
-    pub fn set_direction(&mut self, direction: glm::Vec3) {
-        self.direction_to_camera = direction;
+    pub fn update_view(&mut self) {
+        self.view = glm::look_at(
+            &self.position,
+            &(&self.position + &self.direction_to_camera),
+            &self.up(),
+        );
     }
 
-    pub fn set_position(&mut self, position: glm::Vec3) {
-        self.position = position;
+    // right, up
+    pub fn basis(&self) -> (glm::Vec3, glm::Vec3) {
+        let right = glm::normalize(&glm::cross(&self.direction_to_camera, &self.up()));
+        let up = glm::cross(&right, &self.direction_to_camera);
+
+        (right, up)
     }
 
-    pub fn set_move_x(&mut self, movement: CameraMovement) {
-        self.move_x = movement;
+    // in degrees, y is up
+    pub fn yaw_pitch(&self) -> (f32, f32) {
+        let direction = &self.direction_to_camera;
+
+        (
+            direction.z.atan2(direction.x).to_degrees(),
+            direction.y.asin().to_degrees(),
+        )
     }
 
-    pub fn set_move_z(&mut self, movement: CameraMovement) {
-        self.move_z = movement;
+    pub fn set_yaw_pitch(&mut self, yaw: f32, pitch: f32) {
+        let direction = glm::vec3(
+            yaw.to_radians().cos() * pitch.to_radians().cos(),
+            pitch.to_radians().sin(),
+            yaw.to_radians().sin() * pitch.to_radians().cos(),
+        );
+
+        self.direction_to_camera = direction.normalize();
     }
 
-    pub fn set_move_y(&mut self, movement: CameraMovement) {
-        self.move_y = movement;
+    // tan of half fov, vertical and horizontal (perspective)
+    pub fn tan_half_fov(&self) -> (f32, f32) {
+        (1. / self.projection[(1, 1)], 1. / self.projection[(0, 0)])
     }
 
+    pub fn set_direction(&mut self, direction: glm::Vec3) {
+        self.direction_to_camera = direction.normalize();
+    }
+
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+    }
+
+    // Window z is depth as written to depth buffer, 0..1 clip space with reverse-Z
     pub fn world_to_screen(&self, obj: &glm::Vec3) -> glm::Vec3 {
-        glm::project(
-            &obj,
-            &self.view,
-            &self.projection,
-            glm::vec4(0., 0., self.screen_width as f32, self.screen_height as f32),
+        let clip = &self.projection * &self.view * glm::vec4(obj.x, obj.y, obj.z, 1.);
+        let ndc = glm::vec4_to_vec3(&clip) / clip.w;
+
+        glm::vec3(
+            (ndc.x * 0.5 + 0.5) * self.screen_width as f32,
+            (ndc.y * 0.5 + 0.5) * self.screen_height as f32,
+            if self.reverse_z {
+                ndc.z
+            } else {
+                ndc.z * 0.5 + 0.5
+            },
         )
     }
 
     pub fn screen_to_world(&self, obj: &glm::Vec3) -> glm::Vec3 {
-        glm::unproject(
-            &obj,
-            &self.view,
-            &self.projection,
-            glm::vec4(0., 0., self.screen_width as f32, self.screen_height as f32),
-        )
+        let ndc = glm::vec4(
+            obj.x / self.screen_width as f32 * 2. - 1.,
+            obj.y / self.screen_height as f32 * 2. - 1.,
+            if self.reverse_z {
+                obj.z
+            } else {
+                obj.z * 2. - 1.
+            },
+            1.,
+        );
+
+        let world = glm::inverse(&(&self.projection * &self.view)) * ndc;
+        glm::vec4_to_vec3(&world) / world.w
     }
 
     // from pixels to NDC
@@ -188,24 +440,19 @@ impl Camera {
         )
     }
 
-    // inverting y
+    // inverting y, relative to the viewport
     pub fn cursor_to_screen(&self, cursor: &glm::TVec2<i32>) -> glm::Vec2 {
         glm::vec2(
-            cursor.x as f32, //
-            self.screen_height as f32 - cursor.y as f32,
+            cursor.x as f32 * self.cursor_scale - self.viewport_origin.x as f32, //
+            self.window_height as f32
+                - cursor.y as f32 * self.cursor_scale
+                - self.viewport_origin.y as f32,
         )
     }
 
     pub fn line_from_ray(&self, ray: &Ray, length: f32) -> Line2D {
-        let viewport = glm::vec4(0., 0., self.screen_width as f32, self.screen_height as f32);
-
-        let from = glm::project(&ray.origin, &self.view, &self.projection, viewport.clone());
-        let to = glm::project(
-            &(&ray.origin + &ray.dir * length),
-            &self.view,
-            &self.projection,
-            viewport.clone(),
-        );
+        let from = self.world_to_screen(&ray.origin);
+        let to = self.world_to_screen(&(&ray.origin + &ray.dir * length));
 
         // println!("{}", glm::vec3_to_vec2(&to));
 
@@ -225,19 +472,151 @@ impl Camera {
         plane_origin_offset: &glm::Vec3,
     ) -> glm::Vec3 {
         let screen = self.cursor_to_screen(cursor);
-        //
-        // // direction from camera
-        let direction = self.screen_to_world(&glm::vec3(screen.x, screen.y, 1.))
-            - self.screen_to_world(&glm::vec3(screen.x, screen.y, 0.));
 
-        // create plane
+        // ray starts on near plane, so it also works for parallel (orthographic) rays
+        let (near, further) = self.depth_range();
+        let origin = self.screen_to_world(&glm::vec3(screen.x, screen.y, near));
+        let direction = self.screen_to_world(&glm::vec3(screen.x, screen.y, further)) - &origin;
+
+        // intersect with plane
         let nd = glm::dot(&direction, &plane_normal);
-        let pn = glm::dot(&self.position, &plane_normal);
-        // let t = (plane_origin_offset.magnitude() - pn) / nd; // distance
-        let t = (pn) / nd; // distance
+        let t = glm::dot(&(plane_origin_offset - &origin), &plane_normal) / nd; // distance
 
         // get point
-        // &self.position + direction * t
-        &self.position - direction * t
+        origin + direction * t
+    }
+}
+
+// Pose between two logic ticks, alpha 0 is `prev` and 1 is `self`
+impl Interpolatable for Camera {
+    fn interpolate(&self, prev: &Self, alpha: f32) -> Self {
+        let mut camera = self.clone();
+
+        camera.position = glm::lerp(&prev.position, &self.position, alpha);
+        camera.direction_to_camera =
+            glm::slerp(&prev.direction_to_camera, &self.direction_to_camera, alpha);
+        camera.update_view();
+
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cameras() -> Vec<Camera> {
+        let projections = [
+            Projection::perspective_degrees(60.),
+            Projection::Orthographic { half_height: 5. },
+            Projection::OffAxis {
+                left: -0.02,
+                right: 0.08,
+                bottom: -0.05,
+                top: 0.03,
+            },
+        ];
+
+        let mut cameras = vec![];
+        for &projection in &projections {
+            for &reverse_z in &[false, true] {
+                let mut camera = Camera::new();
+                camera.resize(800, 600, 1.);
+                camera.set_position(glm::vec3(3., 4., 8.));
+                camera.set_direction(glm::vec3(-3., -4., -8.));
+                camera.update_view();
+                camera.set_projection(projection);
+                camera.set_reverse_z(reverse_z);
+                cameras.push(camera);
+            }
+        }
+        cameras
+    }
+
+    fn assert_close(a: &glm::Vec3, b: &glm::Vec3, camera: &Camera) {
+        let tolerance = 1e-3 * b.magnitude().max(1.);
+        assert!(
+            (a - b).magnitude() < tolerance,
+            "{:?} reverse-Z {}: {:?} != {:?}",
+            camera.projection_mode,
+            camera.reverse_z,
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn world_screen_round_trip() {
+        let points = [
+            glm::vec3(0., 0., 0.),
+            glm::vec3(1., 0.5, -2.),
+            glm::vec3(-0.5, 1., 1.5),
+        ];
+
+        for camera in &cameras() {
+            for point in &points {
+                let screen = camera.world_to_screen(point);
+                assert!(screen.z > 0. && screen.z < 1., "depth {}", screen.z);
+                assert_close(&camera.screen_to_world(&screen), point, camera);
+            }
+        }
+    }
+
+    #[test]
+    fn screen_world_round_trip() {
+        for camera in &cameras() {
+            let (near, further) = camera.depth_range();
+
+            for &depth in &[near, further, (near + further) / 2.] {
+                let screen = glm::vec3(123., 456., depth);
+                let world = camera.screen_to_world(&screen);
+                assert_close(&camera.world_to_screen(&world), &screen, camera);
+            }
+        }
+    }
+
+    #[test]
+    fn near_plane_depth_follows_reverse_z() {
+        for camera in &cameras() {
+            let near = &camera.position + &camera.direction_to_camera * camera.near_plane;
+            let depth = camera.world_to_screen(&near).z;
+            assert!(
+                (depth - camera.depth_range().0).abs() < 1e-3,
+                "depth {}",
+                depth
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_cast_lands_under_the_cursor() {
+        let normal = glm::vec3(0., 1., 0.);
+        let offset = glm::vec3(0., 0.5, 0.);
+
+        for camera in &cameras() {
+            for cursor in &[glm::vec2(400, 300), glm::vec2(100, 500), glm::vec2(700, 50)] {
+                let hit = camera.cast_cursor_on_plane(cursor, &normal, &offset);
+                assert!((hit.y - 0.5).abs() < 1e-3, "hit {:?}", hit);
+
+                let screen = camera.world_to_screen(&hit);
+                let expected = camera.cursor_to_screen(cursor);
+                assert_close(
+                    &glm::vec3(screen.x, screen.y, 0.),
+                    &glm::vec3(expected.x, expected.y, 0.),
+                    camera,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_is_relative_to_the_viewport() {
+        let mut camera = Camera::new();
+        camera.resize(800, 600, 2.);
+        camera.set_viewport(&Viewport::new(400, 0, 400, 300), 600);
+
+        // window points, y down, to viewport pixels, y up
+        let screen = camera.cursor_to_screen(&glm::vec2(250, 250));
+        assert_eq!((screen.x, screen.y), (100., 100.));
     }
 }
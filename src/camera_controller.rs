@@ -0,0 +1,651 @@
+use crate::camera::{world_up, Camera, EditorView, Projection};
+use crate::input::{Input, Key, MouseButton};
+use crate::spatial_hash::Aabb;
+
+pub trait CameraController {
+    // Called once per logic tick, `dt` in seconds
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32);
+
+    // Pick up current camera pose, so switching controllers does not jump
+    fn activate(&mut self, camera: &Camera);
+}
+
+// Fraction to move towards a target each step, independent of tick rate
+fn ease_factor(rate: f32, dt: f32) -> f32 {
+    1. - (-rate * dt).exp()
+}
+
+/**
+    MOUSE LOOK
+**/
+
+pub struct MouseLook {
+    pub sensitivity: f32, // degrees per pixel
+    pub smoothing: f32,   // 0 is raw input, closer to 1 is smoother
+    yaw: f32,             // y
+    pitch: f32,           // x
+    smoothed_delta: glm::Vec2,
+}
+
+impl MouseLook {
+    pub fn new(camera: &Camera, sensitivity: f32) -> Self {
+        let (yaw, pitch) = camera.yaw_pitch();
+
+        Self {
+            sensitivity,
+            smoothing: 0.,
+            yaw,
+            pitch,
+            smoothed_delta: glm::vec2(0., 0.),
+        }
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &Input) {
+        let delta = input.mouse_delta();
+        self.smoothed_delta = glm::lerp(&delta, &self.smoothed_delta, self.smoothing);
+
+        self.yaw += self.sensitivity * self.smoothed_delta.x;
+        self.pitch += self.sensitivity * self.smoothed_delta.y;
+
+        if self.pitch.abs() > 89.0 {
+            self.pitch = self.pitch.signum() * 89.0;
+        }
+
+        camera.set_yaw_pitch(self.yaw, self.pitch);
+    }
+
+    pub fn reset(&mut self, camera: &Camera) {
+        let (yaw, pitch) = camera.yaw_pitch();
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.smoothed_delta = glm::vec2(0., 0.);
+    }
+}
+
+/**
+    MOTION
+**/
+
+// Velocity that accelerates towards wished direction and damps to rest
+pub struct Motion {
+    pub max_speed: f32,    // units per second
+    pub acceleration: f32, // how fast max speed is reached, 1/s
+    pub damping: f32,      // how fast it stops, 1/s
+    pub sprint_multiplier: f32,
+    velocity: glm::Vec3,
+}
+
+impl Motion {
+    pub fn new(max_speed: f32) -> Self {
+        Self {
+            max_speed,
+            acceleration: 10.,
+            damping: 8.,
+            sprint_multiplier: 3.,
+            velocity: glm::vec3(0., 0., 0.),
+        }
+    }
+
+    // Returns displacement for this tick
+    pub fn step(&mut self, direction: &glm::Vec3, sprint: bool, dt: f32) -> glm::Vec3 {
+        let mut wished = glm::vec3(0., 0., 0.);
+
+        // same speed diagonally
+        if direction.magnitude() > glm::epsilon() {
+            let speed = if sprint {
+                self.max_speed * self.sprint_multiplier
+            } else {
+                self.max_speed
+            };
+
+            wished = direction.normalize() * speed;
+        }
+
+        let rate = if wished.magnitude() > glm::epsilon() {
+            self.acceleration
+        } else {
+            self.damping
+        };
+
+        self.velocity = glm::lerp(&self.velocity, &wished, ease_factor(rate, dt));
+        &self.velocity * dt
+    }
+
+    pub fn stop(&mut self) {
+        self.velocity = glm::vec3(0., 0., 0.);
+    }
+}
+
+/**
+    FLY
+**/
+
+pub struct FlyController {
+    pub look: MouseLook,
+    pub motion: Motion,
+}
+
+impl FlyController {
+    pub fn new(camera: &Camera) -> Self {
+        Self {
+            look: MouseLook::new(camera, 0.1),
+            motion: Motion::new(3.),
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        if input.is_button_down(MouseButton::Right) {
+            self.look.update(camera, input);
+        }
+
+        // wheel adjusts speed
+        if input.wheel() != 0 {
+            self.motion.max_speed = (self.motion.max_speed * 1.2f32.powi(input.wheel()))
+                .max(0.1)
+                .min(100.);
+        }
+
+        let (right, _) = camera.basis();
+        let direction = right * input.axis(Key::Left, Key::Right)
+            + &camera.direction_to_camera * input.axis(Key::Backward, Key::Forward)
+            + world_up() * input.axis(Key::Down, Key::Up);
+
+        camera.position += self.motion.step(&direction, input.is_down(Key::Sprint), dt);
+        camera.update_view();
+    }
+
+    fn activate(&mut self, camera: &Camera) {
+        self.look.reset(camera);
+        self.motion.stop();
+    }
+}
+
+/**
+    FIRST PERSON
+**/
+
+// Walks on a horizontal plane at fixed eye height
+pub struct FirstPersonController {
+    pub look: MouseLook,
+    pub motion: Motion,
+    eye_height: f32,
+    forward: glm::Vec3, // last walking direction, kept while looking straight up or down
+}
+
+impl FirstPersonController {
+    pub fn new(camera: &Camera, eye_height: f32) -> Self {
+        Self {
+            look: MouseLook::new(camera, 0.1),
+            motion: Motion::new(2.5),
+            eye_height,
+            forward: level_forward(camera, &glm::vec3(0., 0., -1.)),
+        }
+    }
+}
+
+// View direction flattened onto the ground, `fallback` when there is nothing left of it
+fn level_forward(camera: &Camera, fallback: &glm::Vec3) -> glm::Vec3 {
+    let level = glm::vec3(
+        camera.direction_to_camera.x,
+        0.,
+        camera.direction_to_camera.z,
+    );
+
+    if glm::length2(&level) > 1e-6 {
+        glm::normalize(&level)
+    } else {
+        *fallback
+    }
+}
+
+impl CameraController for FirstPersonController {
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        if input.is_button_down(MouseButton::Right) {
+            self.look.update(camera, input);
+        }
+
+        self.forward = level_forward(camera, &self.forward);
+        let forward = self.forward;
+        let right = glm::cross(&forward, &world_up());
+
+        let direction = right * input.axis(Key::Left, Key::Right)
+            + forward * input.axis(Key::Backward, Key::Forward);
+
+        camera.position += self.motion.step(&direction, input.is_down(Key::Sprint), dt);
+        camera.position.y += (self.eye_height - camera.position.y) * ease_factor(10., dt);
+
+        camera.update_view();
+    }
+
+    fn activate(&mut self, camera: &Camera) {
+        self.look.reset(camera);
+        self.motion.stop();
+        self.forward = level_forward(camera, &self.forward);
+    }
+}
+
+/**
+    ORBIT
+**/
+
+pub struct OrbitController {
+    pub target: glm::Vec3,
+    distance: f32,
+    min_distance: f32,
+    pub look: MouseLook,
+
+    framing: Option<(glm::Vec3, f32)>, // target and distance to ease into
+}
+
+impl OrbitController {
+    pub fn new(camera: &Camera) -> Self {
+        let distance = glm::length(&camera.position);
+
+        Self {
+            target: &camera.position + &camera.direction_to_camera * distance,
+            distance,
+            min_distance: camera.near_plane * 2.,
+            look: MouseLook::new(camera, 0.3),
+            framing: None,
+        }
+    }
+
+    // Orbit around point, it is put on the view axis at its current depth to avoid a jump
+    pub fn orbit_around(&mut self, camera: &Camera, point: &glm::Vec3) {
+        let depth = glm::dot(&(point - &camera.position), &camera.direction_to_camera);
+
+        self.activate(camera);
+        self.distance = depth.max(self.min_distance);
+        self.target = &camera.position + &camera.direction_to_camera * self.distance;
+        self.framing = Some((point.clone(), self.distance));
+    }
+
+    // Smoothly move target and distance so the whole box is visible
+    pub fn frame(&mut self, camera: &mut Camera, aabb: &Aabb) {
+        let center = (&aabb.min + &aabb.max) * 0.5;
+        let radius = glm::distance(&aabb.min, &aabb.max) * 0.5;
+
+        // use narrower of the two fovs
+        let (tan_half_fov_y, tan_half_fov_x) = camera.tan_half_fov();
+        let half_fov = tan_half_fov_y.min(tan_half_fov_x).atan();
+
+        let distance = (radius / half_fov.sin()).max(self.min_distance);
+        self.framing = Some((center, distance));
+
+        if let Projection::Orthographic { .. } = camera.projection_mode {
+            let aspect = camera.screen_width as f32 / camera.screen_height as f32;
+            camera.set_projection(Projection::Orthographic {
+                half_height: radius / aspect.min(1.),
+            });
+        }
+    }
+
+    // Orthographic look along an axis at the current target
+    pub fn editor_view(&mut self, camera: &mut Camera, view: EditorView) {
+        camera.set_editor_view(view, &self.target.clone(), self.distance);
+        self.look.reset(camera);
+        self.framing = None;
+    }
+
+    pub fn toggle_orthographic(&mut self, camera: &mut Camera) {
+        camera.toggle_orthographic(self.distance);
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        let delta = input.mouse_delta();
+
+        if input.is_button_down(MouseButton::Right) {
+            self.look.update(camera, input);
+        } else if input.is_button_down(MouseButton::Middle) {
+            // move target in view plane, scaled so the point under cursor stays under it
+            let (right, up) = camera.basis();
+            let pixel_size = camera.pixel_size(self.distance);
+
+            self.target += (-right * delta.x - up * delta.y) * pixel_size;
+            self.framing = None;
+        }
+
+        if input.wheel() != 0 {
+            let zoom = 0.9f32.powi(input.wheel());
+
+            // distance does not change size in orthographic view
+            if let Projection::Orthographic { half_height } = camera.projection_mode {
+                camera.set_projection(Projection::Orthographic {
+                    half_height: half_height * zoom,
+                });
+            }
+
+            self.distance = (self.distance * zoom).max(self.min_distance);
+
+            if let Some((_, distance)) = &mut self.framing {
+                *distance = self.distance;
+            }
+        }
+
+        if let Some((target, distance)) = self.framing {
+            let ease = ease_factor(7., dt);
+            self.target = glm::lerp(&self.target, &target, ease);
+            self.distance += (distance - self.distance) * ease;
+
+            if glm::distance(&self.target, &target) < 0.001
+                && (self.distance - distance).abs() < 0.001
+            {
+                self.framing = None;
+            }
+        }
+
+        camera.position = &self.target - &camera.direction_to_camera * self.distance;
+        camera.update_view();
+    }
+
+    // Target goes on the view axis, position and direction stay
+    fn activate(&mut self, camera: &Camera) {
+        self.look.reset(camera);
+        self.target = &camera.position + &camera.direction_to_camera * self.distance;
+        self.framing = None;
+    }
+}
+
+/**
+    SWITCHING
+**/
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ControllerKind {
+    Fly,
+    Orbit,
+    FirstPerson,
+}
+
+pub struct CameraControllers {
+    pub fly: FlyController,
+    pub orbit: OrbitController,
+    pub first_person: FirstPersonController,
+    pub kind: ControllerKind,
+}
+
+impl CameraControllers {
+    pub fn new(camera: &Camera) -> Self {
+        Self {
+            fly: FlyController::new(camera),
+            orbit: OrbitController::new(camera),
+            first_person: FirstPersonController::new(camera, 1.7),
+            kind: ControllerKind::Fly,
+        }
+    }
+
+    pub fn active(&mut self) -> &mut dyn CameraController {
+        match self.kind {
+            ControllerKind::Fly => &mut self.fly,
+            ControllerKind::Orbit => &mut self.orbit,
+            ControllerKind::FirstPerson => &mut self.first_person,
+        }
+    }
+
+    pub fn switch(&mut self, kind: ControllerKind, camera: &Camera) {
+        self.kind = kind;
+        self.active().activate(camera);
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &Input, dt: f32) {
+        self.active().update(camera, input, dt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1. / 60.;
+
+    // At (0, 0, 5) looking down -z at the origin
+    fn camera() -> Camera {
+        let mut camera = Camera::new();
+        camera.set_position(glm::vec3(0., 0., 5.));
+        camera.set_direction(glm::vec3(0., 0., -1.));
+        camera.update_view();
+        camera
+    }
+
+    // Logic ticks for `seconds`, deltas are consumed by the first one
+    fn run(
+        controller: &mut dyn CameraController,
+        camera: &mut Camera,
+        input: &mut Input,
+        seconds: f32,
+    ) {
+        for _ in 0..(seconds / DT).round() as usize {
+            controller.update(camera, input, DT);
+            input.clear_deltas();
+        }
+    }
+
+    fn assert_close(a: &glm::Vec3, b: &glm::Vec3, tolerance: f32) {
+        assert!(glm::distance(a, b) < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn fly_accelerates_and_comes_to_rest() {
+        let mut camera = camera();
+        let mut fly = FlyController::new(&camera);
+        let mut input = Input::new();
+
+        input.press(Key::Forward);
+        run(&mut fly, &mut camera, &mut input, 2.);
+
+        // close to max speed of 3 most of the time, 5 to 6 units
+        assert!(
+            camera.position.z < 0. && camera.position.z > -1.,
+            "{:?}",
+            camera.position
+        );
+        assert!(camera.position.x.abs() < 1e-4 && camera.position.y.abs() < 1e-4);
+
+        input.release(Key::Forward);
+        run(&mut fly, &mut camera, &mut input, 3.);
+        let rest = camera.position;
+        run(&mut fly, &mut camera, &mut input, 1.);
+        assert_close(&camera.position, &rest, 1e-4);
+    }
+
+    #[test]
+    fn fly_strafes_faster_when_sprinting() {
+        let mut walking = camera();
+        let mut fly = FlyController::new(&walking);
+        let mut input = Input::new();
+
+        input.press(Key::Right);
+        input.press(Key::Up);
+        run(&mut fly, &mut walking, &mut input, 1.);
+        let walked = walking.position.x;
+        assert!(walked > 0. && (walking.position.y - walked).abs() < 1e-4);
+
+        // right and up at once are not faster than one of them
+        assert!(walked < 3.);
+
+        let mut sprinting = camera();
+        fly.activate(&sprinting);
+        input.press(Key::Sprint);
+        run(&mut fly, &mut sprinting, &mut input, 1.);
+        assert!(sprinting.position.x > 2.5 * walked);
+    }
+
+    #[test]
+    fn fly_wheel_changes_speed() {
+        let camera = camera();
+        let mut fly = FlyController::new(&camera);
+        let mut input = Input::new();
+
+        input.scroll(2);
+        run(&mut fly, &mut camera.clone(), &mut input, DT);
+        assert!((fly.motion.max_speed - 3. * 1.44).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mouse_look_needs_right_button() {
+        let mut camera = camera();
+        let mut fly = FlyController::new(&camera);
+        let mut input = Input::new();
+
+        input.move_cursor(100, 0);
+        run(&mut fly, &mut camera, &mut input, DT);
+        assert_close(&camera.direction_to_camera, &glm::vec3(0., 0., -1.), 1e-4);
+
+        // 0.1 degrees per pixel, looking down -z is yaw -90
+        input.press_button(MouseButton::Right);
+        input.move_cursor(200, 0);
+        run(&mut fly, &mut camera, &mut input, DT);
+        let (yaw, pitch) = camera.yaw_pitch();
+        assert!(
+            (yaw + 80.).abs() < 1e-3 && pitch.abs() < 1e-3,
+            "{} {}",
+            yaw,
+            pitch
+        );
+
+        // cursor up is pitch up, clamped short of straight up
+        input.move_cursor(200, -10_000);
+        run(&mut fly, &mut camera, &mut input, DT);
+        assert!((camera.yaw_pitch().1 - 89.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn first_person_walks_level_at_eye_height() {
+        let mut camera = camera();
+        camera.set_position(glm::vec3(0., 5., 5.));
+        camera.set_direction(glm::vec3(0., -1., -1.));
+        camera.update_view();
+
+        let mut walker = FirstPersonController::new(&camera, 1.7);
+        let mut input = Input::new();
+
+        input.press(Key::Forward);
+        run(&mut walker, &mut camera, &mut input, 2.);
+
+        // looking down does not walk into the ground
+        assert!(
+            (camera.position.y - 1.7).abs() < 1e-3,
+            "{:?}",
+            camera.position
+        );
+        assert!(camera.position.x.abs() < 1e-4 && camera.position.z < 1.);
+    }
+
+    #[test]
+    fn first_person_survives_a_vertical_view() {
+        // e.g. switched to from the top editor view
+        let mut camera = camera();
+        camera.set_direction(EditorView::Top.direction());
+        camera.update_view();
+
+        let mut walker = FirstPersonController::new(&camera, 1.7);
+        walker.activate(&camera);
+        let mut input = Input::new();
+
+        input.press(Key::Forward);
+        run(&mut walker, &mut camera, &mut input, 1.);
+
+        assert!(
+            camera.position.iter().all(|c| c.is_finite()),
+            "{:?}",
+            camera.position
+        );
+        assert!(camera.position.z < 5., "{:?}", camera.position);
+        assert!(camera.position.x.abs() < 1e-4);
+
+        // keeps the last level direction when looking straight down later
+        camera.set_direction(glm::vec3(1., 0., 0.));
+        camera.update_view();
+        input.release(Key::Forward);
+        run(&mut walker, &mut camera, &mut input, 2.);
+
+        camera.set_direction(glm::vec3(0., 1., 0.));
+        camera.update_view();
+        let before = camera.position;
+        input.press(Key::Forward);
+        run(&mut walker, &mut camera, &mut input, 1.);
+
+        assert!(camera.position.iter().all(|c| c.is_finite()));
+        assert!(camera.position.x > before.x + 0.1, "{:?}", camera.position);
+        assert!((camera.position.z - before.z).abs() < 1e-3);
+    }
+
+    #[test]
+    fn orbit_keeps_distance_to_target() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new(&camera);
+        let mut input = Input::new();
+
+        // 0.3 degrees per pixel, quarter turn from -z to +x
+        input.press_button(MouseButton::Right);
+        input.move_cursor(300, 0);
+        run(&mut orbit, &mut camera, &mut input, DT);
+
+        assert_close(&camera.direction_to_camera, &glm::vec3(1., 0., 0.), 1e-4);
+        assert_close(&camera.position, &glm::vec3(-5., 0., 0.), 1e-3);
+
+        input.release_button(MouseButton::Right);
+        input.scroll(1);
+        run(&mut orbit, &mut camera, &mut input, DT);
+        assert_close(&camera.position, &glm::vec3(-4.5, 0., 0.), 1e-3);
+    }
+
+    #[test]
+    fn orbit_pans_in_the_view_plane() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new(&camera);
+        let mut input = Input::new();
+
+        // dragging left moves the camera right, target follows
+        input.press_button(MouseButton::Middle);
+        input.move_cursor(-100, 0);
+        run(&mut orbit, &mut camera, &mut input, DT);
+
+        let moved = camera.pixel_size(5.) * 100.;
+        assert_close(&orbit.target, &glm::vec3(moved, 0., 0.), 1e-4);
+        assert_close(&camera.position, &glm::vec3(moved, 0., 5.), 1e-4);
+    }
+
+    #[test]
+    fn orbit_frames_a_box() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new(&camera);
+        let mut input = Input::new();
+
+        let center = glm::vec3(10., 0., 0.);
+        orbit.frame(
+            &mut camera,
+            &Aabb::from_center(&center, &glm::vec3(1., 1., 1.)),
+        );
+        run(&mut orbit, &mut camera, &mut input, 3.);
+
+        // eased onto the box, still looking the same way
+        assert_close(&orbit.target, &center, 1e-2);
+        assert_close(&camera.direction_to_camera, &glm::vec3(0., 0., -1.), 1e-4);
+        assert!(glm::distance(&camera.position, &center) > 3f32.sqrt());
+    }
+
+    #[test]
+    fn switching_controllers_does_not_jump() {
+        let mut camera = camera();
+        let mut controllers = CameraControllers::new(&camera);
+        let mut input = Input::new();
+
+        input.press(Key::Left);
+        for _ in 0..30 {
+            controllers.update(&mut camera, &input, DT);
+        }
+        input.release(Key::Left);
+
+        let pose = (camera.position, camera.direction_to_camera);
+        controllers.switch(ControllerKind::Orbit, &camera);
+        controllers.update(&mut camera, &input, DT);
+
+        assert_close(&camera.position, &pose.0, 1e-4);
+        assert_close(&camera.direction_to_camera, &pose.1, 1e-4);
+    }
+}
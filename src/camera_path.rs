@@ -0,0 +1,581 @@
+use crate::camera::Camera;
+use std::cell::{Cell, Ref, RefCell};
+use std::fs;
+use std::path::Path;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PathInterpolation {
+    CatmullRom, // passes through every keyframe
+    Bezier, // cubic segments through every keyframe, handles sized to the segment so uneven spacing doesn't overshoot
+}
+
+impl PathInterpolation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PathInterpolation::CatmullRom => "catmull-rom",
+            PathInterpolation::Bezier => "bezier",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Keyframe {
+    pub position: glm::Vec3,
+    pub rotation: glm::Quat,
+}
+
+impl Keyframe {
+    pub fn from_camera(camera: &Camera) -> Self {
+        let (right, up) = camera.basis();
+        let back = -&camera.direction_to_camera;
+
+        // camera looks down -z
+        let basis = glm::mat3(
+            right.x, up.x, back.x, //
+            right.y, up.y, back.y, //
+            right.z, up.z, back.z, //
+        );
+
+        Self {
+            position: camera.position,
+            rotation: glm::mat3_to_quat(&basis),
+        }
+    }
+
+    pub fn direction(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.rotation, &glm::vec3(0., 0., -1.))
+    }
+}
+
+// Samples per keyframe span for the arc-length table
+const SAMPLES_PER_SPAN: usize = 32;
+
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    pub interpolation: PathInterpolation,
+
+    // (curve parameter, distance along path), ascending, rebuilt before sampling when dirty
+    arc_lengths: RefCell<Vec<(f32, f32)>>,
+    dirty: Cell<bool>,
+}
+
+impl CameraPath {
+    pub fn new(interpolation: PathInterpolation) -> Self {
+        Self {
+            keyframes: vec![],
+            interpolation,
+            arc_lengths: RefCell::new(vec![]),
+            dirty: Cell::new(false),
+        }
+    }
+
+    pub fn push(&mut self, keyframe: Keyframe) {
+        self.keyframes.push(keyframe);
+        self.dirty.set(true);
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.dirty.set(true);
+    }
+
+    pub fn length(&self) -> f32 {
+        self.arc_lengths().last().map(|&(_, s)| s).unwrap_or(0.)
+    }
+
+    // Curve parameter goes from 0 to keyframes - 1
+    fn max_param(&self) -> f32 {
+        (self.keyframes.len().max(1) - 1) as f32
+    }
+
+    fn arc_lengths(&self) -> Ref<'_, Vec<(f32, f32)>> {
+        if self.dirty.replace(false) {
+            self.rebuild_arc_lengths();
+        }
+
+        self.arc_lengths.borrow()
+    }
+
+    fn rebuild_arc_lengths(&self) {
+        let mut table = self.arc_lengths.borrow_mut();
+        table.clear();
+
+        if self.keyframes.is_empty() {
+            return;
+        }
+
+        let samples = (self.keyframes.len() - 1) * SAMPLES_PER_SPAN;
+        let mut previous = self.position_at_param(0.);
+        let mut length = 0.;
+
+        table.push((0., 0.));
+
+        for i in 1..=samples {
+            let u = self.max_param() * i as f32 / samples as f32;
+            let position = self.position_at_param(u);
+
+            length += glm::distance(&previous, &position);
+            table.push((u, length));
+            previous = position;
+        }
+    }
+
+    // Parameter for distance along path, linear between table entries
+    fn param_at_distance(&self, distance: f32) -> f32 {
+        let distance = distance.max(0.).min(self.length());
+        let arc_lengths = self.arc_lengths();
+        let i = arc_lengths
+            .iter()
+            .position(|&(_, s)| s >= distance)
+            .unwrap_or(0);
+
+        if i == 0 {
+            return 0.;
+        }
+
+        let (u0, s0) = arc_lengths[i - 1];
+        let (u1, s1) = arc_lengths[i];
+
+        if s1 - s0 < glm::epsilon() {
+            u1
+        } else {
+            u0 + (u1 - u0) * (distance - s0) / (s1 - s0)
+        }
+    }
+
+    // Segment i goes from keyframe i to i + 1, only its neighbours are looked at
+    fn position_at_param(&self, u: f32) -> glm::Vec3 {
+        let keys = &self.keyframes;
+        let last = keys.len() - 1;
+        let i = (u.floor() as usize).min(last.max(1) - 1);
+        let t = u - i as f32;
+
+        // end points are repeated
+        let p0 = &keys[i.max(1) - 1].position;
+        let p1 = &keys[i].position;
+        let p2 = &keys[(i + 1).min(last)].position;
+        let p3 = &keys[(i + 2).min(last)].position;
+
+        match self.interpolation {
+            PathInterpolation::CatmullRom => catmull_rom(p0, p1, p2, p3, t),
+            PathInterpolation::Bezier => {
+                // Catmull-Rom tangent directions, a third of the segment long
+                let length = glm::distance(p1, p2) / 3.;
+                let c1 = p1 + handle(&(p2 - p0), length);
+                let c2 = p2 - handle(&(p3 - p1), length);
+
+                cubic_bezier(p1, &c1, &c2, p2, t)
+            }
+        }
+    }
+
+    fn rotation_at_param(&self, u: f32) -> glm::Quat {
+        let last = self.keyframes.len() - 1;
+        let i = (u.floor() as usize).min(last);
+        let t = u - i as f32;
+
+        let q1 = &self.keyframes[i].rotation;
+        let mut q2 = self.keyframes[(i + 1).min(last)].rotation;
+
+        // shortest way around
+        if glm::quat_dot(q1, &q2) < 0. {
+            q2 = -q2;
+        }
+
+        glm::quat_normalize(&glm::quat_slerp(q1, &q2, t))
+    }
+
+    // Sample at distance along path, so constant playback speed means constant camera speed
+    pub fn sample(&self, distance: f32) -> Option<Keyframe> {
+        if self.keyframes.is_empty() {
+            return None;
+        }
+
+        let u = self.param_at_distance(distance);
+
+        Some(Keyframe {
+            position: self.position_at_param(u),
+            rotation: self.rotation_at_param(u),
+        })
+    }
+
+    /**
+        Text format, one line per entry:
+        interpolation catmull-rom
+        key px py pz qx qy qz qw
+    **/
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut text = format!("interpolation {}\n", self.interpolation.as_str());
+
+        for key in &self.keyframes {
+            let p = &key.position;
+            let q = &key.rotation.coords; // x, y, z, w
+
+            text.push_str(&format!(
+                "key {} {} {} {} {} {} {}\n",
+                p.x, p.y, p.z, q.x, q.y, q.z, q.w
+            ));
+        }
+
+        fs::write(path, text)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CameraPath, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut camera_path = CameraPath::new(PathInterpolation::CatmullRom);
+
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some("interpolation") => {
+                    camera_path.interpolation = match words.next() {
+                        Some("catmull-rom") => PathInterpolation::CatmullRom,
+                        Some("bezier") => PathInterpolation::Bezier,
+                        other => {
+                            return Err(format!(
+                                "line {}: unknown interpolation {:?}",
+                                number + 1,
+                                other
+                            ))
+                        }
+                    }
+                }
+                Some("key") => {
+                    let values = words
+                        .map(|w| w.parse::<f32>())
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|e| format!("line {}: {}", number + 1, e))?;
+
+                    if values.len() != 7 {
+                        return Err(format!("line {}: expected 7 numbers", number + 1));
+                    }
+
+                    // hand-edited files rarely have unit quaternions
+                    let rotation = glm::quat(values[3], values[4], values[5], values[6]);
+
+                    let finite = rotation.coords.iter().all(|c| c.is_finite());
+                    if !finite || glm::quat_length(&rotation) < glm::epsilon() {
+                        return Err(format!("line {}: invalid rotation", number + 1));
+                    }
+
+                    camera_path.push(Keyframe {
+                        position: glm::vec3(values[0], values[1], values[2]),
+                        rotation: glm::quat_normalize(&rotation),
+                    });
+                }
+                Some(word) if !word.starts_with('#') => {
+                    return Err(format!("line {}: unknown entry {}", number + 1, word));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(camera_path)
+    }
+}
+
+fn catmull_rom(
+    p0: &glm::Vec3,
+    p1: &glm::Vec3,
+    p2: &glm::Vec3,
+    p3: &glm::Vec3,
+    t: f32,
+) -> glm::Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    (p1 * 2.
+        + (p2 - p0) * t
+        + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * t2
+        + (-p0 + p1 * 3. - p2 * 3. + p3) * t3)
+        * 0.5
+}
+
+fn cubic_bezier(
+    p0: &glm::Vec3,
+    p1: &glm::Vec3,
+    p2: &glm::Vec3,
+    p3: &glm::Vec3,
+    t: f32,
+) -> glm::Vec3 {
+    let s = 1. - t;
+
+    p0 * (s * s * s) + p1 * (3. * s * s * t) + p2 * (3. * s * t * t) + p3 * (t * t * t)
+}
+
+// `length` along `tangent`, none when the neighbours coincide
+fn handle(tangent: &glm::Vec3, length: f32) -> glm::Vec3 {
+    if glm::length2(tangent) > 1e-12 {
+        glm::normalize(tangent) * length
+    } else {
+        glm::vec3(0., 0., 0.)
+    }
+}
+
+/**
+    PLAYBACK
+**/
+
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub speed: f32, // units per second
+    pub looping: bool,
+
+    distance: f32,
+    playing: bool,
+}
+
+impl CameraPathPlayer {
+    pub fn new(path: CameraPath, speed: f32) -> Self {
+        Self {
+            path,
+            speed,
+            looping: false,
+            distance: 0.,
+            playing: false,
+        }
+    }
+
+    pub fn play(&mut self) {
+        if self.distance >= self.path.length() {
+            self.distance = 0.;
+        }
+
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.distance = 0.;
+    }
+
+    pub fn seek(&mut self, distance: f32) {
+        self.distance = distance.max(0.).min(self.path.length());
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // 0..1
+    pub fn progress(&self) -> f32 {
+        if self.path.length() > 0. {
+            self.distance / self.path.length()
+        } else {
+            0.
+        }
+    }
+
+    // Moves camera along the path, call once per logic tick
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        if !self.playing {
+            return;
+        }
+
+        self.distance += self.speed * dt;
+
+        if self.distance >= self.path.length() {
+            if self.looping && self.path.length() > 0. {
+                self.distance %= self.path.length();
+            } else {
+                self.distance = self.path.length();
+                self.playing = false;
+            }
+        }
+
+        if let Some(key) = self.path.sample(self.distance) {
+            camera.position = key.position;
+            camera.set_direction(key.direction());
+            camera.update_view();
+        }
+    }
+}
+
+/**
+    RECORDING
+**/
+
+// Takes a keyframe from the camera every `interval` seconds
+pub struct CameraPathRecorder {
+    pub interval: f32,
+    path: Option<CameraPath>,
+    elapsed: f32,
+}
+
+impl CameraPathRecorder {
+    pub fn new(interval: f32) -> Self {
+        Self {
+            interval,
+            path: None,
+            elapsed: 0.,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn start(&mut self, camera: &Camera) {
+        let mut path = CameraPath::new(PathInterpolation::CatmullRom);
+        path.push(Keyframe::from_camera(camera));
+
+        self.path = Some(path);
+        self.elapsed = 0.;
+    }
+
+    pub fn stop(&mut self, camera: &Camera) -> Option<CameraPath> {
+        let mut path = self.path.take()?;
+        path.push(Keyframe::from_camera(camera));
+        Some(path)
+    }
+
+    pub fn update(&mut self, camera: &Camera, dt: f32) {
+        if let Some(path) = &mut self.path {
+            self.elapsed += dt;
+
+            if self.elapsed >= self.interval {
+                self.elapsed -= self.interval;
+                path.push(Keyframe::from_camera(camera));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(x: f32) -> Keyframe {
+        Keyframe {
+            position: glm::vec3(x, 0., 0.),
+            rotation: glm::quat_identity(),
+        }
+    }
+
+    #[test]
+    fn arc_lengths_rebuild_lazily() {
+        let mut path = CameraPath::new(PathInterpolation::CatmullRom);
+
+        for i in 0..100 {
+            path.push(key(i as f32));
+        }
+
+        assert!(path.dirty.get());
+        assert!(path.arc_lengths.borrow().is_empty());
+
+        assert!((path.length() - 99.).abs() < 1e-3);
+        assert!(!path.dirty.get());
+
+        path.push(key(100.));
+        assert!((path.length() - 100.).abs() < 1e-3);
+
+        path.clear();
+        assert_eq!(path.length(), 0.);
+        assert!(path.sample(0.).is_none());
+    }
+
+    #[test]
+    fn samples_by_distance() {
+        let mut path = CameraPath::new(PathInterpolation::CatmullRom);
+        // repeated end points slow the curve down near the ends, distance must not
+        for i in 0..4 {
+            path.push(key(i as f32 * 3.));
+        }
+
+        for &distance in &[0., 0.5, 4., 9.] {
+            let x = path.sample(distance).unwrap().position.x;
+            assert!((x - distance).abs() < 1e-2, "{} at {}", x, distance);
+        }
+    }
+
+    #[test]
+    fn bezier_passes_through_keyframes() {
+        let mut path = CameraPath::new(PathInterpolation::Bezier);
+        let points = [
+            glm::vec3(0., 0., 0.),
+            glm::vec3(1., 0., 0.),
+            glm::vec3(1., 0., 8.),
+            glm::vec3(9., 2., 8.),
+        ];
+
+        for &position in &points {
+            path.push(Keyframe {
+                position,
+                rotation: glm::quat_identity(),
+            });
+        }
+
+        for (i, point) in points.iter().enumerate() {
+            let position = path.position_at_param(i as f32);
+            assert!(glm::distance(&position, point) < 1e-5, "{:?}", position);
+        }
+
+        // short first segment stays short, no loop out towards the far keyframes
+        for step in 0..=10 {
+            let position = path.position_at_param(step as f32 / 10.);
+            assert!(
+                glm::distance(&position, &points[0]) <= 1.5,
+                "{:?}",
+                position
+            );
+        }
+
+        // smooth: same direction leaving and entering each inner keyframe
+        for i in 1..3 {
+            let u = i as f32;
+            let before = path.position_at_param(u) - path.position_at_param(u - 1e-3);
+            let after = path.position_at_param(u + 1e-3) - path.position_at_param(u);
+            assert!(glm::dot(&glm::normalize(&before), &glm::normalize(&after)) > 0.99);
+        }
+    }
+
+    #[test]
+    fn long_recordings_sample_quickly() {
+        let mut path = CameraPath::new(PathInterpolation::Bezier);
+
+        for i in 0..500 {
+            let angle = i as f32 * 0.05;
+            path.push(Keyframe {
+                position: glm::vec3(angle.cos() * 10., i as f32 * 0.01, angle.sin() * 10.),
+                rotation: glm::quat_identity(),
+            });
+        }
+
+        let start = std::time::Instant::now();
+        assert!(path.length() > 200.);
+        assert!(start.elapsed().as_secs_f32() < 1., "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn load_normalizes_rotations() {
+        let file =
+            std::env::temp_dir().join(format!("camera_path_test_{}.txt", std::process::id()));
+
+        fs::write(
+            &file,
+            "interpolation bezier\nkey 0 0 0 0 0 0 2\nkey 1 2 3 0 3 0 4\n",
+        )
+        .unwrap();
+        let path = CameraPath::load(&file).unwrap();
+
+        assert_eq!(path.interpolation, PathInterpolation::Bezier);
+        assert_eq!(path.keyframes.len(), 2);
+        assert!((path.length() - 14f32.sqrt()).abs() < 1e-3);
+
+        for key in &path.keyframes {
+            assert!((glm::quat_length(&key.rotation) - 1.).abs() < 1e-6);
+        }
+
+        assert!((path.keyframes[1].rotation.coords.y - 0.6).abs() < 1e-6);
+
+        fs::write(&file, "key 0 0 0 0 0 0 0\n").unwrap();
+        assert!(CameraPath::load(&file).is_err());
+
+        fs::remove_file(&file).unwrap();
+    }
+}
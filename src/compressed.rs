@@ -0,0 +1,1007 @@
+use crate::texture::{Texture, TextureError, TextureOptions};
+use gl;
+use std::convert::TryFrom;
+use std::path::Path;
+
+/**
+    DDS and KTX2 containers with block compressed data and a pre-built mip
+    chain. Blocks are 4x4 pixels, BC1 is 8 bytes per block, the rest 16.
+**/
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockFormat {
+    Bc1, // rgb + 1 bit alpha, DXT1
+    Bc3, // rgba, DXT5
+    Bc5, // two channels, normal maps
+    Bc7, // rgba, high quality
+}
+
+impl BlockFormat {
+    pub fn block_size(self) -> usize {
+        match self {
+            BlockFormat::Bc1 => 8,
+            _ => 16,
+        }
+    }
+
+    // Bytes of a `width` x `height` level, partial blocks are padded
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        let blocks_x = ((width.max(1) + 3) / 4) as usize;
+        let blocks_y = ((height.max(1) + 3) / 4) as usize;
+        blocks_x * blocks_y * self.block_size()
+    }
+
+    // BC5 has no sRGB variant
+    pub fn gl_format(self, srgb: bool) -> gl::types::GLenum {
+        match (self, srgb) {
+            (BlockFormat::Bc1, false) => gl::COMPRESSED_RGBA_S3TC_DXT1_EXT,
+            (BlockFormat::Bc1, true) => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT,
+            (BlockFormat::Bc3, false) => gl::COMPRESSED_RGBA_S3TC_DXT5_EXT,
+            (BlockFormat::Bc3, true) => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+            (BlockFormat::Bc5, _) => gl::COMPRESSED_RG_RGTC2,
+            (BlockFormat::Bc7, false) => gl::COMPRESSED_RGBA_BPTC_UNORM,
+            (BlockFormat::Bc7, true) => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+        }
+    }
+}
+
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>, // largest first
+}
+
+// Containers Texture::load hands to CompressedImage instead of stb_image
+pub fn is_container(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => {
+            extension.eq_ignore_ascii_case("dds") || extension.eq_ignore_ascii_case("ktx2")
+        }
+        None => false,
+    }
+}
+
+// RGTC and BPTC are core since 4.2 but drivers may leave them out of
+// COMPRESSED_TEXTURE_FORMATS, S3TC is an extension and has to be asked for
+pub fn is_supported(gl: &gl::GlPtr, format: gl::types::GLenum) -> bool {
+    match format {
+        gl::COMPRESSED_RG_RGTC2
+        | gl::COMPRESSED_RGBA_BPTC_UNORM
+        | gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM => return true,
+        _ => {}
+    }
+
+    let mut supported = gl::FALSE as gl::types::GLint;
+
+    unsafe {
+        gl.GetInternalformativ(
+            gl::TEXTURE_2D,
+            format,
+            gl::INTERNALFORMAT_SUPPORTED,
+            1,
+            &mut supported,
+        );
+    }
+
+    supported == gl::TRUE as gl::types::GLint
+}
+
+// A full chain down to 1x1, more levels in a file means it is corrupt
+fn max_levels(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+fn check_level_count(level_count: u32, width: u32, height: u32) -> Result<(), String> {
+    if level_count > max_levels(width, height) {
+        return Err(format!(
+            "{} levels for a {}x{} image",
+            level_count, width, height
+        ));
+    }
+    Ok(())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Option<u64> {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes.get(offset..offset + 8)?);
+    Some(u64::from_le_bytes(value))
+}
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const KTX2_IDENTIFIER: &[u8] = &[
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+
+impl CompressedImage {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CompressedImage, TextureError> {
+        let name = path.as_ref().to_string_lossy().into_owned();
+        let bytes = std::fs::read(path.as_ref()).map_err(|error| TextureError::Decode {
+            path: name.clone(),
+            reason: error.to_string(),
+        })?;
+
+        Self::parse(&bytes).map_err(|reason| TextureError::Decode { path: name, reason })
+    }
+
+    // Container is recognized by its magic bytes
+    pub fn parse(bytes: &[u8]) -> Result<CompressedImage, String> {
+        if bytes.starts_with(DDS_MAGIC) {
+            Self::parse_dds(bytes)
+        } else if bytes.starts_with(KTX2_IDENTIFIER) {
+            Self::parse_ktx2(bytes)
+        } else {
+            Err("not a DDS or KTX2 file".to_string())
+        }
+    }
+
+    /**
+        DDS
+        "DDS ", 124 byte header, optional 20 byte DX10 header, then the levels
+    **/
+
+    fn parse_dds(bytes: &[u8]) -> Result<CompressedImage, String> {
+        const DDPF_FOURCC: u32 = 0x4;
+        let truncated = || "truncated DDS header".to_string();
+
+        let height = u32_at(bytes, 12).ok_or_else(truncated)?;
+        let width = u32_at(bytes, 16).ok_or_else(truncated)?;
+        let level_count = u32_at(bytes, 28).ok_or_else(truncated)?.max(1);
+        let pixel_flags = u32_at(bytes, 80).ok_or_else(truncated)?;
+        let four_cc = bytes.get(84..88).ok_or_else(truncated)?;
+
+        check_level_count(level_count, width, height)?;
+
+        if pixel_flags & DDPF_FOURCC == 0 {
+            return Err("uncompressed DDS is not supported".to_string());
+        }
+
+        let (format, srgb, data_offset) = match four_cc {
+            b"DXT1" => (BlockFormat::Bc1, false, 128),
+            b"DXT5" => (BlockFormat::Bc3, false, 128),
+            b"ATI2" | b"BC5U" => (BlockFormat::Bc5, false, 128),
+            b"DX10" => {
+                let (format, srgb) = match u32_at(bytes, 128).ok_or_else(truncated)? {
+                    71 => (BlockFormat::Bc1, false),
+                    72 => (BlockFormat::Bc1, true),
+                    77 => (BlockFormat::Bc3, false),
+                    78 => (BlockFormat::Bc3, true),
+                    83 => (BlockFormat::Bc5, false),
+                    98 => (BlockFormat::Bc7, false),
+                    99 => (BlockFormat::Bc7, true),
+                    dxgi => return Err(format!("DXGI format {} is not supported", dxgi)),
+                };
+                (format, srgb, 148)
+            }
+            other => {
+                return Err(format!(
+                    "DDS format {} is not supported",
+                    String::from_utf8_lossy(other)
+                ))
+            }
+        };
+
+        // levels follow each other, largest first
+        let mut offset: usize = data_offset;
+        let mut levels = vec![];
+
+        for level in 0..level_count {
+            let size = format.level_size(width >> level, height >> level);
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| format!("level {} is truncated", level))?;
+
+            levels.push(data.to_vec());
+            offset += size;
+        }
+
+        Ok(CompressedImage {
+            format,
+            srgb,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    /**
+        KTX2
+        identifier, header, index, then a level index of (offset, length,
+        uncompressed length) per level. Only plain 2D textures without
+        supercompression.
+    **/
+
+    fn parse_ktx2(bytes: &[u8]) -> Result<CompressedImage, String> {
+        let truncated = || "truncated KTX2 header".to_string();
+
+        let vk_format = u32_at(bytes, 12).ok_or_else(truncated)?;
+        let width = u32_at(bytes, 20).ok_or_else(truncated)?;
+        let height = u32_at(bytes, 24).ok_or_else(truncated)?;
+        let depth = u32_at(bytes, 28).ok_or_else(truncated)?;
+        let layers = u32_at(bytes, 32).ok_or_else(truncated)?;
+        let faces = u32_at(bytes, 36).ok_or_else(truncated)?;
+        let level_count = u32_at(bytes, 40).ok_or_else(truncated)?.max(1);
+        let supercompression = u32_at(bytes, 44).ok_or_else(truncated)?;
+
+        check_level_count(level_count, width, height)?;
+
+        if depth > 0 || layers > 0 || faces != 1 {
+            return Err("only 2D KTX2 textures are supported".to_string());
+        }
+
+        if supercompression != 0 {
+            return Err(format!(
+                "KTX2 supercompression {} is not supported",
+                supercompression
+            ));
+        }
+
+        let (format, srgb) = match vk_format {
+            131 | 133 => (BlockFormat::Bc1, false),
+            132 | 134 => (BlockFormat::Bc1, true),
+            137 => (BlockFormat::Bc3, false),
+            138 => (BlockFormat::Bc3, true),
+            141 => (BlockFormat::Bc5, false),
+            145 => (BlockFormat::Bc7, false),
+            146 => (BlockFormat::Bc7, true),
+            other => return Err(format!("VkFormat {} is not supported", other)),
+        };
+
+        let mut levels = vec![];
+
+        for level in 0..level_count {
+            let index = 80 + level as usize * 24;
+            let offset = u64_at(bytes, index).ok_or_else(truncated)?;
+            let length = u64_at(bytes, index + 8).ok_or_else(truncated)?;
+
+            if length != format.level_size(width >> level, height >> level) as u64 {
+                return Err(format!("level {} has {} bytes", level, length));
+            }
+
+            // offsets are u64 in the file, anything past the end is rejected
+            let data = offset
+                .checked_add(length)
+                .and_then(|end| {
+                    bytes.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?)
+                })
+                .ok_or_else(|| format!("level {} is truncated", level))?;
+            levels.push(data.to_vec());
+        }
+
+        Ok(CompressedImage {
+            format,
+            srgb,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    pub fn level_dimensions(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // Drops levels larger than `max_size` while smaller ones are left
+    pub fn limit_size(&mut self, max_size: u32) {
+        while self.levels.len() > 1 && self.width.max(self.height) > max_size {
+            self.levels.remove(0);
+            self.width = (self.width / 2).max(1);
+            self.height = (self.height / 2).max(1);
+        }
+    }
+
+    /**
+        CPU DECODER
+        Fallback when the driver does not support the format, normally only
+        S3TC without the extension. BC7 is decoded too for tools and tests.
+    **/
+
+    // RGBA8 pixels of one level, rows top to bottom like the blocks
+    pub fn decode_level(&self, level: usize) -> Result<Vec<u8>, String> {
+        let (width, height) = self.level_dimensions(level);
+        let data = &self.levels[level];
+        let blocks_x = ((width + 3) / 4) as usize;
+        let mut pixels = vec![0u8; width as usize * height as usize * 4];
+
+        for (index, block) in data.chunks(self.format.block_size()).enumerate() {
+            let texels = match self.format {
+                BlockFormat::Bc1 => decode_bc1(block, false),
+                BlockFormat::Bc3 => {
+                    let alpha = decode_bc4(&block[..8]);
+                    let mut texels = decode_bc1(&block[8..], true);
+                    for (texel, &a) in texels.iter_mut().zip(alpha.iter()) {
+                        texel[3] = a;
+                    }
+                    texels
+                }
+                BlockFormat::Bc5 => {
+                    let red = decode_bc4(&block[..8]);
+                    let green = decode_bc4(&block[8..]);
+                    let mut texels = [[0, 0, 0, 255]; 16];
+                    for i in 0..16 {
+                        texels[i][0] = red[i];
+                        texels[i][1] = green[i];
+                    }
+                    texels
+                }
+                BlockFormat::Bc7 => decode_bc7(block),
+            };
+
+            let (block_x, block_y) = ((index % blocks_x) * 4, (index / blocks_x) * 4);
+
+            for (i, texel) in texels.iter().enumerate() {
+                let (x, y) = (block_x + i % 4, block_y + i / 4);
+
+                // partial blocks at the right and bottom edges
+                if x < width as usize && y < height as usize {
+                    let offset = (y * width as usize + x) * 4;
+                    pixels[offset..offset + 4].copy_from_slice(texel);
+                }
+            }
+        }
+
+        Ok(pixels)
+    }
+}
+
+fn color_565(color: u16) -> [u32; 3] {
+    let r = ((color >> 11) & 31) as u32;
+    let g = ((color >> 5) & 63) as u32;
+    let b = (color & 31) as u32;
+    [
+        (r * 255 + 15) / 31,
+        (g * 255 + 31) / 63,
+        (b * 255 + 15) / 31,
+    ]
+}
+
+// Two 565 endpoints and 2-bit indices, `four_colors` for the color block of BC3
+fn decode_bc1(block: &[u8], four_colors: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (color_565(c0), color_565(c1));
+
+    let mut palette = [[0u8; 4]; 4];
+    for channel in 0..3 {
+        let (a, b) = (e0[channel], e1[channel]);
+        palette[0][channel] = a as u8;
+        palette[1][channel] = b as u8;
+
+        if c0 > c1 || four_colors {
+            palette[2][channel] = ((2 * a + b) / 3) as u8;
+            palette[3][channel] = ((a + 2 * b) / 3) as u8;
+        } else {
+            palette[2][channel] = ((a + b) / 2) as u8;
+        }
+    }
+    palette[0][3] = 255;
+    palette[1][3] = 255;
+    palette[2][3] = 255;
+    palette[3][3] = if c0 > c1 || four_colors { 255 } else { 0 };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0u8; 4]; 16];
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (2 * i)) & 3) as usize];
+    }
+
+    texels
+}
+
+// One channel, two 8-bit endpoints and 3-bit indices (BC3 alpha, BC5 channels)
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u32; 8];
+    palette[0] = a0;
+    palette[1] = a1;
+
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as u32) * a0 + i as u32 * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as u32) * a0 + i as u32 * a1) / 5;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = 0u64;
+    for (i, &byte) in block[2..8].iter().enumerate() {
+        bits |= (byte as u64) << (8 * i);
+    }
+
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((bits >> (3 * i)) & 7) as usize] as u8;
+    }
+
+    values
+}
+
+/**
+    BC7
+    Eight modes with 1 to 3 subsets, each subset interpolates between two
+    endpoints. Fields are packed LSB first in the order read below.
+**/
+
+// Subsets, partition bits, rotation bits, index selection bit, color bits,
+// alpha bits, p-bit per endpoint, p-bit per subset, index bits, secondary index bits
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bit: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const fn bc7_mode(
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bit: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        selection_bit,
+        color_bits,
+        alpha_bits,
+        endpoint_pbits,
+        shared_pbits,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+
+static BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    bc7_mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    bc7_mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    bc7_mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    bc7_mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    bc7_mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    bc7_mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    bc7_mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+// Bit i set when texel i belongs to the second subset
+static BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, //
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000, //
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce, //
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, //
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a, //
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660, //
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, //
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22, //
+];
+
+// Subset of each texel, two bits per texel
+static BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+// Texel whose index is stored with one bit less, per partition
+static BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, //
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2, //
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, //
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15, //
+];
+
+static BC7_ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, //
+    3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15, //
+    8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, //
+    3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3, //
+];
+
+static BC7_ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, //
+    15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8, //
+    15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, //
+    15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8, //
+];
+
+fn bc7_weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &[0, 21, 43, 64],
+        3 => &[0, 9, 18, 27, 37, 46, 55, 64],
+        _ => &[0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64],
+    }
+}
+
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+fn bc7_subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        2 => ((BC7_PARTITIONS_2[partition] >> texel) & 1) as usize,
+        3 => ((BC7_PARTITIONS_3[partition] >> (2 * texel)) & 3) as usize,
+        _ => 0,
+    }
+}
+
+fn bc7_is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0
+        || match subsets {
+            2 => texel == BC7_ANCHORS_2[partition] as usize,
+            3 => {
+                texel == BC7_ANCHORS_3_SECOND[partition] as usize
+                    || texel == BC7_ANCHORS_3_THIRD[partition] as usize
+            }
+            _ => false,
+        }
+}
+
+// Widens `bits` to 8 by repeating the high bits
+fn bc7_expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&block[..16]);
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(bytes),
+        position: 0,
+    };
+
+    // mode is the number of zero bits before the first set one
+    let mode = match (0..8).find(|_| reader.read(1) == 1) {
+        Some(mode) => &BC7_MODES[mode],
+        None => return [[0; 4]; 16], // reserved, transparent black
+    };
+
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let selection = reader.read(mode.selection_bit);
+
+    // [endpoint][channel], endpoints 2 * subset and 2 * subset + 1
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = if mode.alpha_bits > 0 {
+            reader.read(mode.alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    // p-bits are the lowest bit of every channel of the endpoint
+    let mut pbits = [0u32; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits.iter_mut().take(endpoint_count) {
+            *pbit = reader.read(1);
+        }
+    } else if mode.shared_pbits {
+        for subset in 0..mode.subsets {
+            let pbit = reader.read(1);
+            pbits[2 * subset] = pbit;
+            pbits[2 * subset + 1] = pbit;
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+
+    for (endpoint, &pbit) in endpoints.iter_mut().zip(pbits.iter()).take(endpoint_count) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let bits = if channel == 3 {
+                mode.alpha_bits
+            } else {
+                mode.color_bits
+            };
+
+            if bits == 0 {
+                continue;
+            }
+            if has_pbits {
+                *value = bc7_expand((*value << 1) | pbit, bits + 1);
+            } else {
+                *value = bc7_expand(*value, bits);
+            }
+        }
+    }
+
+    let read_indices = |reader: &mut BitReader, bits: u32| {
+        let mut indices = [0u32; 16];
+        for (texel, index) in indices.iter_mut().enumerate() {
+            *index = if bc7_is_anchor(mode.subsets, partition, texel) {
+                reader.read(bits - 1)
+            } else {
+                reader.read(bits)
+            };
+        }
+        indices
+    };
+
+    let primary = read_indices(&mut reader, mode.index_bits);
+    let secondary = if mode.secondary_index_bits > 0 {
+        Some(read_indices(&mut reader, mode.secondary_index_bits))
+    } else {
+        None
+    };
+
+    // mode 4 can swap which index set drives color and which alpha
+    let (color_bits, alpha_bits) = match secondary {
+        Some(_) if selection == 1 => (mode.secondary_index_bits, mode.index_bits),
+        Some(_) => (mode.index_bits, mode.secondary_index_bits),
+        None => (mode.index_bits, mode.index_bits),
+    };
+    let (color_indices, alpha_indices) = match secondary {
+        Some(secondary) if selection == 1 => (secondary, primary),
+        Some(secondary) => (primary, secondary),
+        None => (primary, primary),
+    };
+
+    let interpolate = |e0: u32, e1: u32, weight: u32| ((64 - weight) * e0 + weight * e1 + 32) >> 6;
+
+    let mut texels = [[0u8; 4]; 16];
+
+    for (texel, output) in texels.iter_mut().enumerate() {
+        let subset = bc7_subset(mode.subsets, partition, texel);
+        let (e0, e1) = (endpoints[2 * subset], endpoints[2 * subset + 1]);
+        let color_weight = bc7_weights(color_bits)[color_indices[texel] as usize];
+        let alpha_weight = bc7_weights(alpha_bits)[alpha_indices[texel] as usize];
+
+        for channel in 0..3 {
+            output[channel] = interpolate(e0[channel], e1[channel], color_weight) as u8;
+        }
+        output[3] = interpolate(e0[3], e1[3], alpha_weight) as u8;
+
+        // alpha was stored in place of the rotated channel
+        match rotation {
+            1 => output.swap(0, 3),
+            2 => output.swap(1, 3),
+            3 => output.swap(2, 3),
+            _ => {}
+        }
+    }
+
+    texels
+}
+
+/**
+    SELF TEST
+**/
+
+const FIXTURES: &str = "res/test/compressed";
+
+// Parses and decodes the fixtures, uploads what the driver supports,
+// `--test-compressed` runs it
+pub fn self_test(gl: &gl::GlPtr) -> Result<(), String> {
+    let fixture = |name: &str| CompressedImage::load(format!("{}/{}", FIXTURES, name));
+
+    // red, blue and the two colors between them, one row each
+    let gradient: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 0, 255, 255],
+        [170, 0, 85, 255],
+        [85, 0, 170, 255],
+    ];
+
+    let expect = |what: &str, got: &[u8], expected: &[u8]| {
+        if got == expected {
+            Ok(())
+        } else {
+            Err(format!("{}: expected {:?}, got {:?}", what, expected, got))
+        }
+    };
+
+    // 8x8 with mips down to 1x1
+    let bc1 = fixture("bc1_mips.dds")?;
+    let sizes: Vec<usize> = bc1.levels.iter().map(|level| level.len()).collect();
+
+    if bc1.format != BlockFormat::Bc1 || bc1.srgb || sizes != [32, 8, 8, 8] {
+        return Err(format!(
+            "bc1_mips.dds: parsed as {:?} with levels {:?}",
+            bc1.format, sizes
+        ));
+    }
+
+    let pixels = bc1.decode_level(0)?;
+    for (row, color) in gradient.iter().enumerate() {
+        // second block row repeats the first
+        for &y in &[row, row + 4] {
+            let offset = (y * 8 + 7) * 4;
+            expect("bc1 pixel", &pixels[offset..offset + 4], color)?;
+        }
+    }
+    expect("bc1 1x1 level", &bc1.decode_level(3)?, &gradient[0])?;
+
+    // top half opaque, bottom half transparent
+    let bc3 = fixture("bc3.ktx2")?;
+    if bc3.format != BlockFormat::Bc3 || bc3.srgb {
+        return Err(format!("bc3.ktx2: parsed as {:?}", bc3.format));
+    }
+
+    let pixels = bc3.decode_level(0)?;
+    expect("bc3 opaque", &pixels[0..4], &gradient[0])?;
+    expect("bc3 transparent", &pixels[12 * 4..13 * 4], &[85, 0, 170, 0])?;
+
+    let bc5 = fixture("bc5_dx10.dds")?;
+    expect("bc5 pixel", &bc5.decode_level(0)?[0..4], &[200, 20, 0, 255])?;
+
+    let bc7 = fixture("bc7_srgb.ktx2")?;
+    if bc7.format != BlockFormat::Bc7 || !bc7.srgb || (bc7.width, bc7.height) != (4, 4) {
+        return Err(format!("bc7_srgb.ktx2: parsed as {:?}", bc7.format));
+    }
+
+    // mode 6 gradient, texel i uses index i
+    let pixels = bc7.decode_level(0)?;
+    expect("bc7 first texel", &pixels[0..4], &[255, 1, 1, 255])?;
+    expect("bc7 texel 5", &pixels[5 * 4..6 * 4], &[172, 1, 84, 255])?;
+    expect("bc7 last texel", &pixels[15 * 4..16 * 4], &[1, 1, 255, 255])?;
+
+    // compressed upload or the CPU fallback
+    for name in &["bc1_mips.dds", "bc3.ktx2", "bc5_dx10.dds", "bc7_srgb.ktx2"] {
+        let image = fixture(name)?;
+
+        Texture::from_compressed(gl, &image, &TextureOptions::default())
+            .map_err(|error| format!("{}: {}", name, error))?;
+
+        let error = unsafe { gl.GetError() };
+        if error != gl::NO_ERROR {
+            return Err(format!(
+                "{}: upload failed with GL error {:#x}",
+                name, error
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BC1_MIPS: &[u8] = include_bytes!("../res/test/compressed/bc1_mips.dds");
+    const BC3: &[u8] = include_bytes!("../res/test/compressed/bc3.ktx2");
+    const BC5_DX10: &[u8] = include_bytes!("../res/test/compressed/bc5_dx10.dds");
+    const BC7_SRGB: &[u8] = include_bytes!("../res/test/compressed/bc7_srgb.ktx2");
+
+    fn texel(pixels: &[u8], width: usize, x: usize, y: usize) -> &[u8] {
+        let offset = (y * width + x) * 4;
+        &pixels[offset..offset + 4]
+    }
+
+    // Packs (value, bits) fields LSB first like a BC7 block
+    fn pack(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, count) in fields {
+            bits |= (value as u128) << position;
+            position += count;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn parses_dds_with_mips() {
+        let image = CompressedImage::parse(BC1_MIPS).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc1);
+        assert!(!image.srgb);
+        assert_eq!((image.width, image.height), (8, 8));
+        let sizes: Vec<usize> = image.levels.iter().map(|level| level.len()).collect();
+        assert_eq!(sizes, [32, 8, 8, 8]);
+        assert_eq!(image.level_dimensions(3), (1, 1));
+    }
+
+    #[test]
+    fn parses_ktx2_and_dx10() {
+        let bc3 = CompressedImage::parse(BC3).unwrap();
+        assert_eq!(
+            (bc3.format, bc3.srgb, bc3.width, bc3.height),
+            (BlockFormat::Bc3, false, 4, 4)
+        );
+        assert_eq!(bc3.levels.len(), 1);
+
+        let bc5 = CompressedImage::parse(BC5_DX10).unwrap();
+        assert_eq!(bc5.format, BlockFormat::Bc5);
+        assert_eq!(bc5.levels.len(), 1);
+
+        let bc7 = CompressedImage::parse(BC7_SRGB).unwrap();
+        assert_eq!(
+            (bc7.format, bc7.srgb, bc7.width, bc7.height),
+            (BlockFormat::Bc7, true, 4, 4)
+        );
+        assert_eq!(bc7.levels[0].len(), 16);
+    }
+
+    #[test]
+    fn decodes_bc1_gradient() {
+        let image = CompressedImage::parse(BC1_MIPS).unwrap();
+        let pixels = image.decode_level(0).unwrap();
+
+        // red, blue and the two colors between them, one row each
+        assert_eq!(texel(&pixels, 8, 7, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&pixels, 8, 7, 1), [0, 0, 255, 255]);
+        assert_eq!(texel(&pixels, 8, 7, 2), [170, 0, 85, 255]);
+        assert_eq!(texel(&pixels, 8, 0, 7), [85, 0, 170, 255]);
+        assert_eq!(image.decode_level(3).unwrap(), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc3_alpha_and_bc5_channels() {
+        let bc3 = CompressedImage::parse(BC3)
+            .unwrap()
+            .decode_level(0)
+            .unwrap();
+        assert_eq!(texel(&bc3, 4, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&bc3, 4, 0, 3), [85, 0, 170, 0]);
+
+        let bc5 = CompressedImage::parse(BC5_DX10)
+            .unwrap()
+            .decode_level(0)
+            .unwrap();
+        assert_eq!(texel(&bc5, 4, 0, 0), [200, 20, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc1_punch_through_alpha() {
+        // c0 <= c1 selects three colors and transparent black for index 3
+        let block = [0x00, 0x00, 0x1f, 0x00, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(decode_bc1(&block, false)[0], [0, 0, 0, 0]);
+        assert_eq!(decode_bc1(&block, true)[0], [0, 0, 170, 255]);
+    }
+
+    #[test]
+    fn decodes_bc7_mode_6() {
+        let pixels = CompressedImage::parse(BC7_SRGB)
+            .unwrap()
+            .decode_level(0)
+            .unwrap();
+        assert_eq!(texel(&pixels, 4, 0, 0), [255, 1, 1, 255]);
+        assert_eq!(texel(&pixels, 4, 1, 1), [172, 1, 84, 255]);
+        assert_eq!(texel(&pixels, 4, 3, 3), [1, 1, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_bc7_mode_1_partition() {
+        // partition 13, top two rows in the first subset
+        let mut fields = vec![(0b10, 2), (13, 6)];
+        fields.extend_from_slice(&[(63, 6), (63, 6), (0, 6), (0, 6)]); // red
+        fields.extend_from_slice(&[(0, 6); 4]); // green
+        fields.extend_from_slice(&[(0, 6), (0, 6), (63, 6), (63, 6)]); // blue
+        fields.extend_from_slice(&[(1, 1), (1, 1)]); // shared p-bits
+        fields.push((0, 46)); // indices, anchors 0 and 15 have one bit less
+
+        let texels = decode_bc7(&pack(&fields));
+        assert_eq!(texels[0], [255, 2, 2, 255]);
+        assert_eq!(texels[7], [255, 2, 2, 255]);
+        assert_eq!(texels[8], [2, 2, 255, 255]);
+        assert_eq!(texels[15], [2, 2, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_bc7_mode_5_rotation() {
+        // rotation 1 stores red in alpha and alpha in red
+        let mut fields = vec![(0b100000, 6), (1, 2)];
+        fields.extend_from_slice(&[(64, 7), (64, 7), (0, 7), (0, 7), (0, 7), (0, 7)]);
+        fields.extend_from_slice(&[(0, 8), (255, 8)]);
+        fields.push((0, 31)); // color indices
+        fields.push((1, 1)); // alpha anchor, weight 21
+        fields.push((0x3fff_ffff, 30)); // other alpha indices, weight 64
+
+        let texels = decode_bc7(&pack(&fields));
+        assert_eq!(texels[0], [84, 0, 0, 129]);
+        assert_eq!(texels[1], [255, 0, 0, 129]);
+    }
+
+    #[test]
+    fn reserved_bc7_mode_is_transparent() {
+        assert_eq!(decode_bc7(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn bc7_anchors_belong_to_their_subset() {
+        for partition in 0..64 {
+            assert_eq!(
+                bc7_subset(2, partition, BC7_ANCHORS_2[partition] as usize),
+                1
+            );
+            assert_eq!(
+                bc7_subset(3, partition, BC7_ANCHORS_3_SECOND[partition] as usize),
+                1
+            );
+            assert_eq!(
+                bc7_subset(3, partition, BC7_ANCHORS_3_THIRD[partition] as usize),
+                2
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        for fixture in &[BC1_MIPS, BC3, BC5_DX10, BC7_SRGB] {
+            for &length in &[4, 20, 100, fixture.len() - 1] {
+                assert!(CompressedImage::parse(&fixture[..length]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_level_count() {
+        // 8x8 has at most 4 levels, 40 would shift past 32 bits
+        let mut dds = BC1_MIPS.to_vec();
+        dds[28..32].copy_from_slice(&40u32.to_le_bytes());
+        assert!(CompressedImage::parse(&dds)
+            .err()
+            .unwrap()
+            .contains("levels"));
+
+        dds[28..32].copy_from_slice(&5u32.to_le_bytes());
+        assert!(CompressedImage::parse(&dds).is_err());
+
+        let mut ktx2 = BC3.to_vec();
+        ktx2[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(CompressedImage::parse(&ktx2)
+            .err()
+            .unwrap()
+            .contains("levels"));
+    }
+
+    #[test]
+    fn rejects_level_offsets_past_the_end() {
+        let mut ktx2 = BC3.to_vec();
+        ktx2[80..88].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(CompressedImage::parse(&ktx2).is_err());
+    }
+
+    #[test]
+    fn max_levels_is_a_full_chain() {
+        assert_eq!(max_levels(1, 1), 1);
+        assert_eq!(max_levels(8, 8), 4);
+        assert_eq!(max_levels(8, 3), 4);
+        assert_eq!(max_levels(0, 0), 1);
+        assert_eq!(max_levels(u32::MAX, 1), 32);
+    }
+}
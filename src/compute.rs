@@ -0,0 +1,189 @@
+use crate::shader::Program;
+use crate::texture::Texture;
+use gl;
+use std::marker::PhantomData;
+
+/**
+    SHADER STORAGE BUFFERS
+**/
+
+// Typed buffer, `T` has to match the std430 layout in the shader (use vec4 instead of vec3)
+pub struct StorageBuffer<T: Copy> {
+    gl: gl::GlPtr,
+    pub id: gl::types::GLuint,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> StorageBuffer<T> {
+    pub fn new(gl: &gl::GlPtr, data: &[T]) -> Self {
+        let mut id = 0;
+
+        unsafe {
+            gl.GenBuffers(1, &mut id);
+        }
+
+        let mut buffer = Self {
+            gl: gl.clone(),
+            id,
+            len: 0,
+            _marker: PhantomData,
+        };
+        buffer.upload(data);
+        buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn size_of(len: usize) -> gl::types::GLsizeiptr {
+        (len * std::mem::size_of::<T>()) as gl::types::GLsizeiptr
+    }
+
+    // Reallocates only when the length changes
+    pub fn upload(&mut self, data: &[T]) {
+        unsafe {
+            self.gl.BindBuffer(gl::SHADER_STORAGE_BUFFER, self.id);
+
+            if data.len() == self.len {
+                self.gl.BufferSubData(
+                    gl::SHADER_STORAGE_BUFFER,
+                    0,
+                    Self::size_of(data.len()),
+                    data.as_ptr() as *const gl::types::GLvoid,
+                );
+            } else {
+                self.gl.BufferData(
+                    gl::SHADER_STORAGE_BUFFER,
+                    Self::size_of(data.len()),
+                    data.as_ptr() as *const gl::types::GLvoid,
+                    gl::DYNAMIC_COPY,
+                );
+            }
+
+            self.gl.BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+
+        self.len = data.len();
+    }
+
+    // Waits for the GPU, call `memory_barrier(BUFFER_UPDATE_BARRIER_BIT)` after writes first
+    pub fn read(&self) -> Vec<T> {
+        let mut data: Vec<T> = Vec::with_capacity(self.len);
+
+        unsafe {
+            self.gl.BindBuffer(gl::SHADER_STORAGE_BUFFER, self.id);
+            self.gl.GetBufferSubData(
+                gl::SHADER_STORAGE_BUFFER,
+                0,
+                Self::size_of(self.len),
+                data.as_mut_ptr() as *mut gl::types::GLvoid,
+            );
+            self.gl.BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+            data.set_len(self.len);
+        }
+
+        data
+    }
+
+    // layout (std430, binding = N) buffer
+    pub fn bind_base(&self, binding: u32) {
+        unsafe {
+            self.gl
+                .BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, self.id);
+        }
+    }
+}
+
+impl<T: Copy> Drop for StorageBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/**
+    DISPATCH
+**/
+
+// layout (binding = unit, format) uniform image2D, `access` is READ_ONLY, WRITE_ONLY or READ_WRITE.
+// Cubemaps, arrays and volumes bind every layer, e.g. as imageCube
+pub fn bind_image(
+    gl: &gl::GlPtr,
+    unit: u32,
+    texture: &Texture,
+    access: gl::types::GLenum,
+    format: gl::types::GLenum,
+) {
+    let layered = match texture.target {
+        gl::TEXTURE_CUBE_MAP
+        | gl::TEXTURE_CUBE_MAP_ARRAY
+        | gl::TEXTURE_2D_ARRAY
+        | gl::TEXTURE_3D => gl::TRUE,
+        _ => gl::FALSE,
+    };
+
+    unsafe {
+        gl.BindImageTexture(unit, texture.id, 0, layered, 0, access, format);
+    }
+}
+
+// Binds and runs compute program, group counts are in work groups, not invocations
+pub fn dispatch(gl: &gl::GlPtr, program: &Program, groups_x: u32, groups_y: u32, groups_z: u32) {
+    program.bind();
+
+    unsafe {
+        gl.DispatchCompute(groups_x, groups_y, groups_z);
+    }
+}
+
+// Work groups needed to cover `invocations` with `local_size` per group
+pub fn group_count(invocations: usize, local_size: u32) -> u32 {
+    (invocations as u32).div_ceil(local_size)
+}
+
+// e.g. SHADER_STORAGE_BARRIER_BIT before reading in another dispatch,
+// VERTEX_ATTRIB_ARRAY_BARRIER_BIT before drawing from the buffer
+pub fn memory_barrier(gl: &gl::GlPtr, barriers: gl::types::GLbitfield) {
+    unsafe {
+        gl.MemoryBarrier(barriers);
+    }
+}
+
+/**
+    SELF TEST
+**/
+
+// Doubles numbers on the GPU and reads them back, `--test-compute` runs it
+// (LIBGL_ALWAYS_SOFTWARE=1 for Mesa llvmpipe)
+pub fn self_test(gl: &gl::GlPtr) -> Result<(), String> {
+    let program = Program::from_sources(
+        gl,
+        vec![shader_source!(
+            gl::COMPUTE_SHADER,
+            "shaders/compute/double.comp"
+        )],
+    )?;
+
+    let input: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+    let buffer = StorageBuffer::new(gl, &input);
+    buffer.bind_base(0);
+
+    program.bind();
+    program.set_uniform("count", &(input.len() as u32));
+    dispatch(gl, &program, group_count(input.len(), 64), 1, 1);
+    memory_barrier(gl, gl::BUFFER_UPDATE_BARRIER_BIT);
+
+    let output = buffer.read();
+
+    for (i, (a, b)) in input.iter().zip(output.iter()).enumerate() {
+        if (a * 2. - b).abs() > f32::EPSILON {
+            return Err(format!("element {}: expected {}, got {}", i, a * 2., b));
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 extern crate nalgebra_glm as glm;
+use crate::camera::Camera;
 use crate::cube::Ray;
 use crate::primitives;
 use crate::primitives::Model;
-use crate::shader::{Program, Shader};
+use crate::shader::Program;
 use gl;
 use std::ffi::*;
 
@@ -27,8 +28,7 @@ pub struct Debug<'a> {
 pub struct DebugDrawer<'a> {
     debug: &'a Debug<'a>,
 
-    view: &'a glm::Mat4,
-    proj: &'a glm::Mat4,
+    camera: &'a Camera,
 }
 
 impl<'a> DebugDrawer<'a> {
@@ -47,12 +47,12 @@ impl<'a> DebugDrawer<'a> {
         // pyramid width
         use crate::glm::RealField;
 
-        let test = glm::unproject(
-            &glm::vec3(weight, weight, 0.),
-            &self.view,
-            &self.proj,
-            glm::vec4(0., 0., 1., 1.),
-        );
+        let camera = self.camera;
+        let test = camera.screen_to_world(&glm::vec3(
+            weight * camera.screen_width as f32,
+            weight * camera.screen_height as f32,
+            camera.depth_range().0,
+        ));
 
         let arrow_size = (to - test).magnitude() * weight * 0.02;
 
@@ -100,8 +100,6 @@ impl<'a> DebugDrawer<'a> {
             }
 
             self.debug.shader.setVec4Float(color, "color");
-            self.debug.shader.setMat4(&self.view, "view");
-            self.debug.shader.setMat4(&self.proj, "projection");
             self.debug.shader.setMat4(&model, "model");
 
             self.debug.gl.DrawArrays(gl::LINES, 0, 2);
@@ -195,8 +193,6 @@ impl<'a> DebugDrawer<'a> {
         self.debug
             .shader
             .setVec4Float(&glm::vec4(1., 1., 1., 0.1), "color");
-        self.debug.shader.setMat4(&self.view, "view");
-        self.debug.shader.setMat4(&self.proj, "projection");
         self.debug.shader.setMat4(&model, "model");
 
         self.debug.grid.draw_lines(1.);
@@ -255,19 +251,14 @@ impl<'a> Debug<'a> {
     }
 
     fn gen_line_shader(gl: &gl::GlPtr) -> Program {
-        let vert_shader = Shader::from_vert_source(
+        Program::from_sources(
             &gl,
-            &CString::new(include_str!("shaders/color/color.vert")).unwrap(),
+            vec![
+                shader_source!(gl::VERTEX_SHADER, "shaders/color/color.vert"),
+                shader_source!(gl::FRAGMENT_SHADER, "shaders/color/color.frag"),
+            ],
         )
-        .unwrap();
-
-        let frag_shader = Shader::from_frag_source(
-            &gl,
-            &CString::new(include_str!("shaders/color/color.frag")).unwrap(),
-        )
-        .unwrap();
-
-        Program::from_shaders(&gl, &[vert_shader, frag_shader]).unwrap()
+        .unwrap_or_else(|error| panic!("{}", error))
     }
 
     fn gen_line(gl: &gl::GlPtr) -> (GlInt, GlInt) {
@@ -309,11 +300,10 @@ impl<'a> Debug<'a> {
         (line_vao, line_vbo)
     }
 
-    pub fn setup_drawer(&'a self, view: &'a glm::Mat4, proj: &'a glm::Mat4) -> DebugDrawer<'a> {
+    pub fn setup_drawer(&'a self, camera: &'a Camera) -> DebugDrawer<'a> {
         DebugDrawer {
             debug: &self,
-            view,
-            proj,
+            camera,
         }
     }
 }
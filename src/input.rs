@@ -0,0 +1,175 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use std::collections::HashSet;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    Sprint,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+/**
+    Input state decoupled from SDL, controllers only read from here.
+    Events are translated in `handle_event`, synthetic input can be fed with
+    `press`, `release`, `move_cursor` and `scroll`.
+**/
+pub struct Input {
+    keys: HashSet<Key>,
+    buttons: HashSet<MouseButton>,
+
+    cursor: glm::TVec2<i32>,
+    mouse_delta: glm::Vec2, // accumulated since last `clear_deltas`, y up
+    wheel: i32,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            cursor: glm::vec2(0, 0),
+            mouse_delta: glm::vec2(0., 0.),
+            wheel: 0,
+        }
+    }
+
+    fn map_keycode(keycode: Keycode) -> Option<Key> {
+        match keycode {
+            Keycode::W => Some(Key::Forward),
+            Keycode::S => Some(Key::Backward),
+            Keycode::A => Some(Key::Left),
+            Keycode::D => Some(Key::Right),
+            Keycode::Space => Some(Key::Up),
+            Keycode::LCtrl => Some(Key::Down),
+            Keycode::LShift => Some(Key::Sprint),
+            _ => None,
+        }
+    }
+
+    fn map_button(button: sdl2::mouse::MouseButton) -> Option<MouseButton> {
+        match button {
+            sdl2::mouse::MouseButton::Left => Some(MouseButton::Left),
+            sdl2::mouse::MouseButton::Middle => Some(MouseButton::Middle),
+            sdl2::mouse::MouseButton::Right => Some(MouseButton::Right),
+            _ => None,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => {
+                if let Some(key) = Self::map_keycode(keycode) {
+                    self.press(key);
+                }
+            }
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => {
+                if let Some(key) = Self::map_keycode(keycode) {
+                    self.release(key);
+                }
+            }
+            Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            } => {
+                self.cursor = glm::vec2(x, y);
+
+                if let Some(button) = Self::map_button(mouse_btn) {
+                    self.buttons.insert(button);
+                }
+            }
+            Event::MouseButtonUp { mouse_btn, .. } => {
+                if let Some(button) = Self::map_button(mouse_btn) {
+                    self.buttons.remove(&button);
+                }
+            }
+            Event::MouseMotion { x, y, .. } => self.move_cursor(x, y),
+            Event::MouseWheel { y, .. } => self.scroll(y),
+            _ => {}
+        }
+    }
+
+    /* synthetic input */
+    pub fn press(&mut self, key: Key) {
+        self.keys.insert(key);
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.keys.remove(&key);
+    }
+
+    pub fn press_button(&mut self, button: MouseButton) {
+        self.buttons.insert(button);
+    }
+
+    pub fn release_button(&mut self, button: MouseButton) {
+        self.buttons.remove(&button);
+    }
+
+    pub fn move_cursor(&mut self, x: i32, y: i32) {
+        self.mouse_delta += glm::vec2((x - self.cursor.x) as f32, (self.cursor.y - y) as f32);
+        self.cursor = glm::vec2(x, y);
+    }
+
+    pub fn scroll(&mut self, y: i32) {
+        self.wheel += y;
+    }
+
+    /* state */
+    pub fn is_down(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    // -1, 0 or 1
+    pub fn axis(&self, negative: Key, positive: Key) -> f32 {
+        let mut value = 0.;
+
+        if self.is_down(negative) {
+            value -= 1.;
+        }
+
+        if self.is_down(positive) {
+            value += 1.;
+        }
+
+        value
+    }
+
+    pub fn cursor(&self) -> glm::TVec2<i32> {
+        self.cursor
+    }
+
+    pub fn mouse_delta(&self) -> glm::Vec2 {
+        self.mouse_delta
+    }
+
+    pub fn wheel(&self) -> i32 {
+        self.wheel
+    }
+
+    // Called after deltas were consumed by a logic update
+    pub fn clear_deltas(&mut self) {
+        self.mouse_delta = glm::vec2(0., 0.);
+        self.wheel = 0;
+    }
+}
@@ -7,31 +7,69 @@ extern crate stb_image;
 
 use gl;
 
-use crate::camera::{Camera, CameraMovement};
+use crate::camera::{Camera, EditorView};
+use crate::camera_controller::{CameraControllers, ControllerKind};
+use crate::camera_path::{CameraPath, CameraPathPlayer, CameraPathRecorder};
 use crate::components::TransformComponent;
 use crate::cube::{Line2D, Ray};
-use crate::double_buffer::{DoubleBuffered, SceneBuffer};
+use crate::double_buffer::{DoubleBuffered, Interpolatable, SceneBuffer};
 use crate::gizmo::Gizmo;
+use crate::input::Input;
+use crate::particles::ParticleSystem;
+use crate::render_target::{RenderTarget, RenderTargetOptions};
+use crate::sampler::Sampler;
+use crate::skybox::Skybox;
+use crate::spatial_hash::{Aabb, SpatialHash};
 use crate::text::Font;
-use crate::texture::{Texture, TextureKind};
+use crate::texture::{max_anisotropy, Filter, Texture, TextureKind, TextureOptions};
+use crate::uniform_buffer::{FrameData, LightStd140, UniformBuffer, FRAME_BINDING};
 use crate::utilities::{is_point_on_line2D, is_rays_intersect};
+use crate::viewport::{build_views, ViewLayout, Viewport};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::{Instant, SystemTime};
 
+// first, so shader_source! is visible in every module below
+#[macro_use]
+mod shader;
+
 mod camera;
+mod camera_controller;
+mod camera_path;
 mod components;
+mod compressed;
+mod compute;
 mod cube;
 mod debug;
 mod double_buffer;
 mod gizmo;
+mod input;
+mod particles;
+mod preprocessor;
 mod primitives;
-mod shader;
+mod program_cache;
+mod reflection;
+mod render_target;
+mod sampler;
+mod screenshot;
+mod shader_error;
+mod skybox;
+mod spatial_hash;
 mod sphere;
 mod texture;
+mod uniform;
+mod uniform_buffer;
 mod utilities;
+mod viewport;
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--bench-spatial") {
+        for &objects in &[1000, 5000, 10000] {
+            spatial_hash::benchmark(objects, 100, 100.);
+        }
+        return;
+    }
+
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
 
@@ -52,6 +90,7 @@ fn main() {
         .window("Game", camera.screen_width, camera.screen_height)
         .opengl()
         .resizable()
+        .allow_highdpi()
         .build()
         .unwrap();
 
@@ -60,76 +99,180 @@ fn main() {
         video_subsystem.gl_get_proc_address(s) as *const std::os::raw::c_void
     }));
 
+    // HiDPI drawable can be bigger than window
+    let drawable_size = |window: &sdl2::video::Window| {
+        let (width, height) = window.drawable_size();
+        let (window_width, _) = window.size();
+        (width, height, width as f32 / window_width.max(1) as f32)
+    };
+
+    let (width, height, cursor_scale) = drawable_size(&window);
+    let (mut window_width, mut window_height) = (width, height);
+    camera.resize(width, height, cursor_scale);
+
+    // Reverse-Z: near at 1, infinite far at 0, needs float depth buffer for precision
+    let reverse_z = std::env::args().any(|arg| arg == "--reverse-z");
+    camera.set_reverse_z(reverse_z);
+
+    // V cycles split-screen layouts, second player camera looks at the scene from the side
+    let mut layout = ViewLayout::Single;
+    let mut second_camera = camera.clone();
+    second_camera.set_position(glm::vec3(6., 3., 0.));
+    second_camera.set_direction(glm::vec3(-6., -3., 0.));
+    second_camera.update_view();
+    let depth_format = if reverse_z {
+        gl::DEPTH32F_STENCIL8
+    } else {
+        gl::DEPTH24_STENCIL8
+    };
+
     unsafe {
         gl.Enable(gl::DEPTH_TEST);
         gl.DepthMask(gl::TRUE);
 
+        if reverse_z {
+            gl.ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+        }
+        gl.DepthFunc(camera.depth_func());
+        gl.ClearDepth(camera.clear_depth());
+
         gl.Enable(gl::CULL_FACE);
         // gl.FrontFace(gl::CW);
 
         gl.Enable(gl::MULTISAMPLE);
     }
 
+    // self tests that need a GL context, e.g. with LIBGL_ALWAYS_SOFTWARE=1
+    let self_tests: &[(&str, fn(&gl::GlPtr) -> Result<(), String>)] = &[
+        ("--test-compute", compute::self_test),
+        ("--test-program-cache", program_cache::self_test),
+        ("--test-compressed", compressed::self_test),
+        ("--test-screenshot", screenshot::self_test),
+    ];
+
+    for &(flag, test) in self_tests {
+        if std::env::args().any(|arg| arg == flag) {
+            match test(&gl) {
+                Ok(()) => println!("{}: ok", flag),
+                Err(error) => {
+                    println!("{}: {}", flag, error);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    }
+
     /////////////////////////////////////
     let debug = debug::Debug::new(&gl);
     let mut gizmo = Gizmo::new();
     texture::Texture::init(&gl); // anisotropic
 
     let diffuse_texture =
-        Texture::from(&gl, "res/test/brickwall.jpg").expect("Cannot load texture");
-    let specular_texture =
-        Texture::from(&gl, "res/test/brickwall_specular.jpg").expect("Cannot load texture");
-    let normal_texture =
-        Texture::from(&gl, "res/test/brickwall_normal.jpg").expect("Cannot load texture");
-    let height_texture =
-        Texture::from(&gl, "res/test/brickwall_height.jpg").expect("Cannot load texture");
+        Texture::from(&gl, "res/test/brickwall.jpg").unwrap_or_else(|error| panic!("{}", error));
+    let specular_texture = Texture::from(&gl, "res/test/brickwall_specular.jpg")
+        .unwrap_or_else(|error| panic!("{}", error));
+    let normal_texture = Texture::from(&gl, "res/test/brickwall_normal.jpg")
+        .unwrap_or_else(|error| panic!("{}", error));
+    let height_texture = Texture::from(&gl, "res/test/brickwall_height.jpg")
+        .unwrap_or_else(|error| panic!("{}", error));
+
+    // shared by every material texture binding
+    let material_sampler = Sampler::new(&gl)
+        .wrap(gl::REPEAT)
+        .mipmaps(Filter::Nearest)
+        .anisotropy(max_anisotropy());
 
     let render_cube = primitives::build_cube(
         &gl,
         vec![
-            (&diffuse_texture, TextureKind::Diffuse),
-            (&specular_texture, TextureKind::Specular),
-            (&normal_texture, TextureKind::Normal),
-            (&height_texture, TextureKind::Height),
+            (&diffuse_texture, TextureKind::Diffuse, &material_sampler),
+            (&specular_texture, TextureKind::Specular, &material_sampler),
+            (&normal_texture, TextureKind::Normal, &material_sampler),
+            (&height_texture, TextureKind::Height, &material_sampler),
         ],
         1.0,
         1.0,
     );
 
+    // voxel block types, one layer each of a single array texture
+    let block_textures = or_exit(Texture::layered_from_files(
+        &gl,
+        gl::TEXTURE_2D_ARRAY,
+        &BLOCK_TEXTURES,
+        &TextureOptions::new().max_size(512),
+    ));
+    let render_block = primitives::build_cube(
+        &gl,
+        vec![(&block_textures, TextureKind::Diffuse, &material_sampler)],
+        1.0,
+        1.0,
+    );
+
     let render_pyramid = primitives::build_pyramid(&gl);
     let render_grid = primitives::build_grid(&gl, 30);
     let render_sphere = primitives::build_sphere(
         &gl,
         vec![
-            (&diffuse_texture, TextureKind::Diffuse),
-            (&specular_texture, TextureKind::Specular),
-            (&normal_texture, TextureKind::Normal),
-            (&height_texture, TextureKind::Height),
+            (&diffuse_texture, TextureKind::Diffuse, &material_sampler),
+            (&specular_texture, TextureKind::Specular, &material_sampler),
+            (&normal_texture, TextureKind::Normal, &material_sampler),
+            (&height_texture, TextureKind::Height, &material_sampler),
         ],
     );
 
     /////////////////////////////////////
 
-    let basic_shader = shader::Program::from_files(
+    // M toggles parallax mapping, both variants are compiled up front
+    let parallax_features: &[&str] = &["NORMAL_MAP", "PARALLAX"];
+    let flat_features: &[&str] = &["NORMAL_MAP"];
+    let mut parallax = true;
+
+    let mut basic_shaders = shader::ProgramVariants::new(
         &gl,
-        include_str!("shaders/basic/basic.vert"),
-        include_str!("shaders/basic/basic.frag"),
-    )
-    .unwrap();
+        vec![
+            shader_source!(gl::VERTEX_SHADER, "shaders/basic/basic.vert"),
+            shader_source!(gl::FRAGMENT_SHADER, "shaders/basic/basic.frag"),
+        ],
+    );
+    or_exit(basic_shaders.prepare(parallax_features));
+    or_exit(basic_shaders.prepare(flat_features));
 
-    let screen_shader = shader::Program::from_files(
+    let mut screen_shader = or_exit(shader::Program::from_sources(
         &gl,
-        include_str!("shaders/screen/screen.vert"),
-        include_str!("shaders/screen/screen.frag"),
-    )
-    .unwrap();
+        vec![
+            shader_source!(gl::VERTEX_SHADER, "shaders/screen/screen.vert"),
+            shader_source!(gl::FRAGMENT_SHADER, "shaders/screen/screen.frag"),
+        ],
+    ));
+
+    let mut block_shader = or_exit(shader::Program::from_sources(
+        &gl,
+        vec![
+            shader_source!(gl::VERTEX_SHADER, "shaders/block/block.vert"),
+            shader_source!(gl::FRAGMENT_SHADER, "shaders/block/block.frag"),
+        ],
+    ));
+
+    let mut color_shader = or_exit(shader::Program::from_sources(
+        &gl,
+        vec![
+            shader_source!(gl::VERTEX_SHADER, "shaders/color/color.vert"),
+            shader_source!(gl::FRAGMENT_SHADER, "shaders/color/color.frag"),
+        ],
+    ));
 
-    let color_shader = shader::Program::from_files(
+    let mut particles = ParticleSystem::new(
         &gl,
-        include_str!("shaders/color/color.vert"),
-        include_str!("shaders/color/color.frag"),
-    )
-    .unwrap();
+        or_exit(shader::Program::from_sources(
+            &gl,
+            vec![shader_source!(
+                gl::COMPUTE_SHADER,
+                "shaders/compute/particles.comp"
+            )],
+        )),
+        10_000,
+    );
 
     /////////////////////////////////////
     let font = Font::new(&gl);
@@ -139,94 +282,93 @@ fn main() {
     /////////////////////////////////////
 
     /* Lights Rendering */
-    type GlInt = gl::types::GLuint;
-
-    // Framebuffer
-    let mut fbo: GlInt = 0;
-    let screen_texture_multisampled =
-        Texture::new_multisampled(&gl, camera.screen_width, camera.screen_height, 4);
-
-    unsafe {
-        gl.GenFramebuffers(1, &mut fbo);
-        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
-
-        gl.FramebufferTexture2D(
-            gl::FRAMEBUFFER,
-            gl::COLOR_ATTACHMENT0,
-            gl::TEXTURE_2D_MULTISAMPLE,
-            screen_texture_multisampled.id,
-            0,
-        );
-
-        gl.BindFramebuffer(gl::FRAMEBUFFER, 0); // Added by me
-    }
-
-    // Framebuffer for postprocessing
-    let mut fbo_postprocesing: GlInt = 0;
-    let screen_texture = Texture::new(&gl, camera.screen_width, camera.screen_height);
-
-    unsafe {
-        gl.GenFramebuffers(1, &mut fbo_postprocesing);
-        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo_postprocesing);
-
-        gl.FramebufferTexture2D(
-            gl::FRAMEBUFFER,
-            gl::COLOR_ATTACHMENT0,
-            gl::TEXTURE_2D,
-            screen_texture.id,
-            0,
-        );
-
-        gl.BindFramebuffer(gl::FRAMEBUFFER, 0); // Added by me
-    }
-
-    // Renderbuffer
-    let mut rbo: GlInt = 0;
-    unsafe {
-        gl.GenRenderbuffers(1, &mut rbo);
-        gl.BindRenderbuffer(gl::RENDERBUFFER, rbo);
-
-        gl.RenderbufferStorageMultisample(
-            gl::RENDERBUFFER,
-            4,
-            gl::DEPTH24_STENCIL8,
-            camera.screen_width as gl::types::GLint,
-            camera.screen_height as gl::types::GLint,
-        );
-
-        // attaching, also added by me
-        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
-        gl.FramebufferRenderbuffer(
-            gl::FRAMEBUFFER,
-            gl::DEPTH_STENCIL_ATTACHMENT,
-            gl::RENDERBUFFER,
-            rbo,
-        );
-        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
-        // end - attaching
+    // scene is drawn multisampled, then resolved into a texture the screen quad samples
+    let scene_target = or_exit(RenderTarget::new(
+        &gl,
+        camera.screen_width,
+        camera.screen_height,
+        &RenderTargetOptions::new()
+            .color(gl::RGB8)
+            .depth_stencil(depth_format)
+            .samples(4),
+    ));
+    let resolve_target = or_exit(RenderTarget::new(
+        &gl,
+        camera.screen_width,
+        camera.screen_height,
+        &RenderTargetOptions::new().color(gl::RGB8),
+    ));
+
+    // background, flat clear color when res/skybox has neither environment
+    let mut skybox = or_exit(Skybox::new(&gl));
+    let exists = |path: &str| std::path::Path::new(path).exists();
+    let environment = if exists(ENVIRONMENT_HDR) {
+        skybox.exposure = 1.;
+        Some(skybox::cubemap_from_equirect(&gl, ENVIRONMENT_HDR, 256))
+    } else if ENVIRONMENT_FACES.iter().all(|&face| exists(face)) {
+        Some(
+            Texture::cubemap_from_files(
+                &gl,
+                &ENVIRONMENT_FACES,
+                &TextureOptions::new().srgb().wrap(gl::CLAMP_TO_EDGE),
+            )
+            .map_err(|error| error.to_string()),
+        )
+    } else {
+        None
+    };
+    let environment = environment.and_then(|environment| {
+        environment
+            .map_err(|error| println!("Skybox: {}", error))
+            .ok()
+    });
 
-        gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
-    }
+    /* Projection quad */
+    let screen_sampler = Sampler::new(&gl);
+    let render_quad = primitives::build_quad(
+        &gl,
+        vec![(
+            resolve_target.color(0),
+            TextureKind::Diffuse,
+            &screen_sampler,
+        )],
+    );
 
-    // Check if framebuffer is complete
-    unsafe {
-        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
-        if gl.CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
-            println!("Framebuffer is not complete!")
+    // vertex layouts against what the shaders read
+    for (name, model, program) in &[
+        (
+            "cube",
+            &render_cube,
+            basic_shaders.get(parallax_features).unwrap(),
+        ),
+        (
+            "sphere",
+            &render_sphere,
+            basic_shaders.get(parallax_features).unwrap(),
+        ),
+        ("quad", &render_quad, &screen_shader),
+        ("block", &render_block, &block_shader),
+    ] {
+        if let Err(error) = model.validate(program) {
+            println!("Model {}: {}", name, error);
         }
-        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
 
-        // and postprocessing
-        gl.BindFramebuffer(gl::FRAMEBUFFER, fbo_postprocesing);
-        if gl.CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
-            println!("Framebuffer postprocessing is not complete!")
+    // camera and light for every shader including shaders/common/frame.glsl
+    let frame_uniforms = UniformBuffer::new(&gl, FRAME_BINDING, &FrameData::default());
+
+    for program in &[
+        basic_shaders.get(parallax_features).unwrap(),
+        basic_shaders.get(flat_features).unwrap(),
+        &block_shader,
+        &color_shader,
+        &skybox.program,
+    ] {
+        if let Err(error) = frame_uniforms.check_layout(program, "Frame") {
+            println!("Program {}: {}", program.id(), error);
         }
-        gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
     }
 
-    /* Projection quad */
-    let render_quad = primitives::build_quad(&gl, vec![(&screen_texture, TextureKind::Diffuse)]);
-
     unsafe {
         gl.Viewport(
             0,
@@ -264,6 +406,20 @@ fn main() {
     // gizmo.target(cube_ptr.clone());
     gizmo.target(light_cube_ptr.clone());
 
+    // Broadphase
+    let mut spatial_hash = SpatialHash::new(2.0);
+    let spatial_objects = vec![
+        (
+            spatial_hash.insert_transform(&light_cube_ptr.borrow()),
+            light_cube_ptr.clone(),
+        ),
+        (
+            spatial_hash.insert_transform(&cube_ptr.borrow()),
+            cube_ptr.clone(),
+        ),
+    ];
+    let mut overlaps = 0;
+
     /////////////////////////////////////
     let mut rays = vec![];
     let mut lines = vec![];
@@ -282,6 +438,17 @@ fn main() {
 
     // Events
     let mut event_pump = sdl.event_pump().unwrap();
+    let mut input = Input::new();
+    let mut controllers = CameraControllers::new(&camera);
+    let mut previous_camera = camera.clone();
+
+    // Camera paths
+    let camera_path_file = "camera_path.txt";
+    let mut recorder = CameraPathRecorder::new(0.5);
+    let mut player: Option<CameraPathPlayer> = None;
+
+    // F12 saves the next frame to screenshots/
+    let mut take_screenshot = false;
 
     /////////////////////////////////////
 
@@ -302,128 +469,182 @@ fn main() {
         }
 
         for event in event_pump.poll_iter() {
+            input.handle_event(&event);
+
             match event {
                 sdl2::event::Event::Quit { .. } => break 'main,
-                sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::Z),
+                sdl2::event::Event::Window {
+                    win_event: sdl2::event::WindowEvent::SizeChanged(..),
                     ..
                 } => {
-                    camera.set_direction(glm::vec3(0., 0., -1.));
-                    camera.set_position(glm::vec3(camera.position.x, 0., camera.position.z));
+                    let (width, height, cursor_scale) = drawable_size(&window);
+
+                    if width > 0 && height > 0 {
+                        window_width = width;
+                        window_height = height;
+                        camera.resize(width, height, cursor_scale);
+                        camera.set_viewport(&layout.viewports(width, height)[0], height);
+
+                        scene_target.resize(width, height);
+                        resolve_target.resize(width, height);
+
+                        unsafe {
+                            gl.Viewport(
+                                0,
+                                0,
+                                width as gl::types::GLint,
+                                height as gl::types::GLint,
+                            );
+                        }
+                    }
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::R),
+                    keycode: Some(sdl2::keyboard::Keycode::V),
                     ..
                 } => {
-                    light_color[0] -= 0.1;
+                    layout = layout.next();
+                    camera.set_viewport(
+                        &layout.viewports(window_width, window_height)[0],
+                        window_height,
+                    );
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::G),
+                    keycode: Some(sdl2::keyboard::Keycode::M),
                     ..
-                } => {
-                    light_color[1] -= 0.1;
-                }
+                } => parallax = !parallax,
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::B),
+                    keycode: Some(sdl2::keyboard::Keycode::Z),
                     ..
                 } => {
-                    light_color[2] -= 0.1;
+                    camera.set_direction(glm::vec3(0., 0., -1.));
+                    camera.set_position(glm::vec3(camera.position.x, 0., camera.position.z));
+                    controllers.active().activate(&camera);
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::T),
+                    keycode: Some(sdl2::keyboard::Keycode::O),
                     ..
-                } => {
-                    light_color = [1., 1., 1.];
-                }
+                } => match controllers.kind {
+                    ControllerKind::Orbit => controllers.switch(ControllerKind::Fly, &camera),
+                    _ => {
+                        controllers.switch(ControllerKind::Orbit, &camera);
+                        controllers
+                            .orbit
+                            .orbit_around(&camera, &light_cube_ptr.borrow().position);
+                    }
+                },
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::D),
+                    keycode: Some(sdl2::keyboard::Keycode::F12),
                     ..
-                } => {
-                    camera.set_move_x(CameraMovement::POSITIVE);
-                }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::D),
+                } => take_screenshot = true,
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::P),
+                    ..
+                } => match controllers.kind {
+                    ControllerKind::FirstPerson => controllers.switch(ControllerKind::Fly, &camera),
+                    _ => controllers.switch(ControllerKind::FirstPerson, &camera),
+                },
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::F),
                     ..
                 } => {
-                    match camera.move_x {
-                        CameraMovement::POSITIVE => camera.set_move_x(CameraMovement::NONE),
-                        _ => {}
-                    };
+                    if controllers.kind != ControllerKind::Orbit {
+                        controllers.switch(ControllerKind::Orbit, &camera);
+                    }
+                    controllers
+                        .orbit
+                        .frame(&mut camera, &Aabb::from_transform(&cube_ptr.borrow()));
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::A),
+                    keycode: Some(keycode @ sdl2::keyboard::Keycode::Kp7),
+                    ..
+                }
+                | sdl2::event::Event::KeyDown {
+                    keycode: Some(keycode @ sdl2::keyboard::Keycode::Kp1),
                     ..
-                } => {
-                    camera.set_move_x(CameraMovement::NEGATIVE);
                 }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::A),
+                | sdl2::event::Event::KeyDown {
+                    keycode: Some(keycode @ sdl2::keyboard::Keycode::Kp3),
                     ..
                 } => {
-                    match camera.move_x {
-                        CameraMovement::NEGATIVE => camera.set_move_x(CameraMovement::NONE),
-                        _ => {}
+                    let view = match keycode {
+                        sdl2::keyboard::Keycode::Kp7 => EditorView::Top,
+                        sdl2::keyboard::Keycode::Kp1 => EditorView::Front,
+                        _ => EditorView::Side,
                     };
+
+                    if controllers.kind != ControllerKind::Orbit {
+                        controllers.switch(ControllerKind::Orbit, &camera);
+                    }
+                    controllers.orbit.editor_view(&mut camera, view);
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::W),
+                    keycode: Some(sdl2::keyboard::Keycode::Kp5),
                     ..
                 } => {
-                    camera.set_move_z(CameraMovement::POSITIVE);
-                }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::W),
-                    ..
-                } => {
-                    match camera.move_z {
-                        CameraMovement::POSITIVE => camera.set_move_z(CameraMovement::NONE),
-                        _ => {}
-                    };
+                    if controllers.kind != ControllerKind::Orbit {
+                        controllers.switch(ControllerKind::Orbit, &camera);
+                    }
+                    controllers.orbit.toggle_orthographic(&mut camera);
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::S),
+                    keycode: Some(sdl2::keyboard::Keycode::K),
                     ..
                 } => {
-                    camera.set_move_z(CameraMovement::NEGATIVE);
+                    if recorder.is_recording() {
+                        if let Some(path) = recorder.stop(&camera) {
+                            match path.save(camera_path_file) {
+                                Ok(_) => println!("Camera path saved to {}", camera_path_file),
+                                Err(e) => println!("Cannot save camera path: {}", e),
+                            }
+                            player = Some(CameraPathPlayer::new(path, 3.));
+                        }
+                    } else {
+                        recorder.start(&camera);
+                    }
                 }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::S),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::L),
                     ..
                 } => {
-                    match camera.move_z {
-                        CameraMovement::NEGATIVE => camera.set_move_z(CameraMovement::NONE),
-                        _ => {}
-                    };
+                    if player.is_none() {
+                        match CameraPath::load(camera_path_file) {
+                            Ok(path) => player = Some(CameraPathPlayer::new(path, 3.)),
+                            Err(e) => println!("Cannot load camera path: {}", e),
+                        }
+                    }
+
+                    if let Some(player) = &mut player {
+                        if player.is_playing() {
+                            player.pause();
+                            controllers.active().activate(&camera);
+                        } else {
+                            player.play();
+                        }
+                    }
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::Space),
+                    keycode: Some(sdl2::keyboard::Keycode::R),
                     ..
                 } => {
-                    camera.set_move_y(CameraMovement::POSITIVE);
+                    light_color[0] -= 0.1;
                 }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::Space),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::G),
                     ..
                 } => {
-                    match camera.move_y {
-                        CameraMovement::POSITIVE => camera.set_move_y(CameraMovement::NONE),
-                        _ => {}
-                    };
+                    light_color[1] -= 0.1;
                 }
                 sdl2::event::Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::LCtrl),
+                    keycode: Some(sdl2::keyboard::Keycode::B),
                     ..
                 } => {
-                    camera.set_move_y(CameraMovement::NEGATIVE);
+                    light_color[2] -= 0.1;
                 }
-                sdl2::event::Event::KeyUp {
-                    keycode: Some(sdl2::keyboard::Keycode::LCtrl),
+                sdl2::event::Event::KeyDown {
+                    keycode: Some(sdl2::keyboard::Keycode::T),
                     ..
                 } => {
-                    match camera.move_y {
-                        CameraMovement::NEGATIVE => camera.set_move_y(CameraMovement::NONE),
-                        _ => {}
-                    };
+                    light_color = [1., 1., 1.];
                 }
                 sdl2::event::Event::MouseButtonDown {
                     mouse_btn: sdl2::mouse::MouseButton::Left,
@@ -435,19 +656,15 @@ fn main() {
                 }
                 sdl2::event::Event::MouseButtonDown {
                     mouse_btn: sdl2::mouse::MouseButton::Right,
-                    x,
-                    y,
                     ..
                 } => {
                     sdl.mouse().show_cursor(false);
-                    camera.click(x, y);
                 }
                 sdl2::event::Event::MouseButtonUp {
                     mouse_btn: sdl2::mouse::MouseButton::Right,
                     ..
                 } => {
                     sdl.mouse().show_cursor(true);
-                    camera.unclick();
                 }
                 sdl2::event::Event::MouseButtonUp {
                     mouse_btn: sdl2::mouse::MouseButton::Left,
@@ -458,7 +675,6 @@ fn main() {
                     });
                 }
                 sdl2::event::Event::MouseMotion { x, y, .. } => {
-                    camera.handle_mouse(x, y);
                     gizmo.drag(&camera, x, y);
                 }
                 _ => {}
@@ -470,7 +686,27 @@ fn main() {
                 break 'logic;
             }
 
-            camera.update();
+            previous_camera = camera.clone();
+            match &mut player {
+                Some(player) if player.is_playing() => {
+                    player.update(&mut camera, s_per_update);
+
+                    if !player.is_playing() {
+                        controllers.active().activate(&camera);
+                    }
+                }
+                _ => controllers.update(&mut camera, &input, s_per_update),
+            }
+            recorder.update(&camera, s_per_update);
+            input.clear_deltas();
+
+            for (handle, transform) in &spatial_objects {
+                spatial_hash.update_transform(*handle, &transform.borrow());
+            }
+            overlaps = spatial_hash.pairs().len();
+
+            particles.emitter = light_cube_ptr.borrow().position;
+            particles.update(s_per_update);
 
             updates += 1;
             // update
@@ -478,6 +714,33 @@ fn main() {
         }
 
         let alpha: f32 = lag / s_per_update;
+        let render_camera = camera.interpolate(&previous_camera, alpha);
+        let views = build_views(
+            layout,
+            &render_camera,
+            &second_camera,
+            window_width,
+            window_height,
+        );
+
+        // edited shaders are picked up without a rebuild in debug builds
+        basic_shaders.reload_if_changed();
+        for program in &mut [
+            &mut screen_shader,
+            &mut block_shader,
+            &mut color_shader,
+            &mut skybox.program,
+        ] {
+            program.reload_if_changed();
+        }
+
+        let basic_shader = basic_shaders
+            .get(if parallax {
+                parallax_features
+            } else {
+                flat_features
+            })
+            .unwrap();
 
         // ************************* RENDERING **********************8**
         let bg = utilities::color_from_rgba(172, 196, 191, 1.);
@@ -487,143 +750,161 @@ fn main() {
             gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
 
-        // 1. Drawing on added offscreen framebuffer (with depth and stencil)
-        unsafe {
-            gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        // 1. Drawing on added offscreen framebuffer (with depth and stencil), once per view
+        scene_target.bind();
+
+        // same light for every view, camera goes to the frame uniforms per view
+        let light = LightStd140 {
+            position: (&light_cube_ptr.borrow().position).into(),
+            ambient: (&glm::vec3(0.5, 0.5, 0.5)).into(),
+            diffuse: (&glm::vec3(light_color[0], light_color[1], light_color[2])).into(),
+            specular: (&glm::vec3(0.5, 0.5, 0.5)).into(),
+        };
+
+        for view in &views {
+            let render_camera = &view.camera;
+            frame_uniforms.update(&FrameData::new(render_camera, light));
+            render_camera.viewport().apply(&gl);
+            render_camera
+                .viewport()
+                .clear(&gl, &bg, render_camera.clear_depth());
+
+            unsafe {
+                gl.Enable(gl::DEPTH_TEST);
+
+                // gl.Enable(gl::CULL_FACE);
+                gl.Disable(gl::CULL_FACE); // Disable faceculling when drawing normal maps
+                gl.FrontFace(gl::CW);
+            }
 
-            gl.ClearColor(bg.x, bg.y, bg.z, bg.w);
-            gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-            gl.Enable(gl::DEPTH_TEST);
+            // render light
+            color_shader.bind();
+            color_shader.setVec4Float(
+                &glm::vec4(light_color[0], light_color[1], light_color[2], 1.),
+                "color",
+            );
+            color_shader.setMat4(&light_cube_ptr.borrow().mat4(), "model");
+            render_sphere.draw(&screen_shader);
+
+            // Render to offscreen buffer
+            basic_shader.bind();
+
+            // material
+            basic_shader.setVec3Float(&glm::vec3(1.0, 0.5, 0.31), "material.ambient");
+            basic_shader.setVec3Float(&glm::vec3(1.0, 0.5, 0.31), "material.diffuse");
+            basic_shader.setVec3Float(&glm::vec3(0.5, 0.5, 0.5), "material.specular");
+            basic_shader.setFloat(32.0, "material.shininess");
+            // basic_shader.setFloat(0.015, "height_scale");
+            basic_shader.setFloat(0.03, "height_scale");
+
+            for cube in &cubes {
+                basic_shader.setMat4(&cube.borrow().mat4(), "model");
+                render_cube.draw(&basic_shader);
+            }
 
-            // gl.Enable(gl::CULL_FACE);
-            gl.Disable(gl::CULL_FACE); // Disable faceculling when drawing normal maps
-            gl.FrontFace(gl::CW);
-        }
+            // a row of blocks, one of each type
+            block_shader.bind();
+            for layer in 0..BLOCK_TEXTURES.len() {
+                let block = TransformComponent::new(
+                    glm::vec3(layer as f32 - 1., 0.3, -3.),
+                    glm::quat_identity(),
+                    glm::vec3(0.5, 0.5, 0.5),
+                );
+                block_shader.setMat4(&block.mat4(), "model");
+                block_shader.setFloat(layer as f32, "layer");
+                render_block.draw(&block_shader);
+            }
+            basic_shader.bind();
 
-        // render light
-        color_shader.bind();
-        color_shader.setVec4Float(
-            &glm::vec4(light_color[0], light_color[1], light_color[2], 1.),
-            "color",
-        );
-        color_shader.setMat4(&light_cube_ptr.borrow().mat4(), "model");
-        render_sphere.draw(&screen_shader);
-
-        // Render to offscreen buffer
-        basic_shader.bind();
-        basic_shader.setMat4(&camera.projection, "projection");
-        basic_shader.setMat4(&camera.view, "view");
-
-        // material
-        basic_shader.setVec3Float(&glm::vec3(1.0, 0.5, 0.31), "material.ambient");
-        basic_shader.setVec3Float(&glm::vec3(1.0, 0.5, 0.31), "material.diffuse");
-        basic_shader.setVec3Float(&glm::vec3(0.5, 0.5, 0.5), "material.specular");
-        basic_shader.setFloat(32.0, "material.shininess");
-        // basic_shader.setFloat(0.015, "height_scale");
-        basic_shader.setFloat(0.03, "height_scale");
-
-        // light
-        basic_shader.setVec3Float(&light_cube_ptr.borrow().position, "light.position");
-        basic_shader.setVec3Float(&glm::vec3(0.5, 0.5, 0.5), "light.ambient");
-        basic_shader.setVec3Float(
-            &glm::vec3(light_color[0], light_color[1], light_color[2]),
-            "light.diffuse",
-        );
-        basic_shader.setVec3Float(&glm::vec3(0.5, 0.5, 0.5), "light.specular");
-        basic_shader.setVec3Float(&camera.position, "viewPos");
+            let drawer = debug.setup_drawer(&render_camera);
+            let floor = TransformComponent::new(
+                glm::vec3(0., 0., 0.),
+                glm::quat_identity(),
+                glm::vec3(5.0, 0.1, 5.0),
+            );
+            basic_shader.setMat4(&floor.mat4(), "model");
+            render_cube.draw(&screen_shader);
 
-        for cube in &cubes {
-            basic_shader.setMat4(&cube.borrow().mat4(), "model");
-            render_cube.draw(&basic_shader);
-        }
+            // sphere
+            unsafe {
+                gl.FrontFace(gl::CCW);
+            }
 
-        let drawer = debug.setup_drawer(&camera.view, &camera.projection);
-        let floor = TransformComponent::new(
-            glm::vec3(0., 0., 0.),
-            glm::quat_identity(),
-            glm::vec3(5.0, 0.1, 5.0),
-        );
-        basic_shader.setMat4(&floor.mat4(), "model");
-        render_cube.draw(&screen_shader);
+            let sphere = TransformComponent::new(
+                glm::vec3(-2., 0.5, 0.),
+                // glm::quat_angle_axis(glm::half_pi(), &glm::vec3(0., 0., 0.)),
+                glm::quat_identity(),
+                glm::vec3(0.5, 0.5, 0.5),
+            );
+            basic_shader.setMat4(&sphere.mat4(), "model");
+            render_sphere.draw(&screen_shader);
+            // render_sphere.draw_mesh(1.5);
 
-        // sphere
-        unsafe {
-            gl.FrontFace(gl::CCW);
-        }
+            // opaque geometry is done, only background pixels are left
+            if let Some(environment) = &environment {
+                skybox.draw(environment, render_camera);
+            }
 
-        let sphere = TransformComponent::new(
-            glm::vec3(-2., 0.5, 0.),
-            // glm::quat_angle_axis(glm::half_pi(), &glm::vec3(0., 0., 0.)),
-            glm::quat_identity(),
-            glm::vec3(0.5, 0.5, 0.5),
-        );
-        basic_shader.setMat4(&sphere.mat4(), "model");
-        render_sphere.draw(&screen_shader);
-        // render_sphere.draw_mesh(1.5);
-
-        // let mut sphere_model = glm::translate(&glm::one(), &glm::vec3(0., 0., 0.));
-        // sphere_model *= glm::scaling(&glm::vec3(1., 1., 1.));
-        //
-        // unsafe {
-        //     gl.FrontFace(gl::CCW);
-        // }
-        //
-        // color_shader.bind();
-        // color_shader.setMat4(&camera.projection, "projection");
-        // color_shader.setMat4(&camera.view, "view");
-        // color_shader.setMat4(&sphere_model, "model");
-        // color_shader.setVec4Float(&glm::vec4(1., 1., 1., 0.5), "color");
-
-        for ray in &rays {
-            drawer.draw_ray(ray, 50.);
-        }
+            // let mut sphere_model = glm::translate(&glm::one(), &glm::vec3(0., 0., 0.));
+            // sphere_model *= glm::scaling(&glm::vec3(1., 1., 1.));
+            //
+            // unsafe {
+            //     gl.FrontFace(gl::CCW);
+            // }
+            //
+            // color_shader.bind();
+            // color_shader.setMat4(&render_camera.projection, "projection");
+            // color_shader.setMat4(&render_camera.view, "view");
+            // color_shader.setMat4(&sphere_model, "model");
+            // color_shader.setVec4Float(&glm::vec4(1., 1., 1., 0.5), "color");
+
+            for ray in &rays {
+                drawer.draw_ray(ray, 50.);
+            }
 
-        for &(a1, a2) in &lines {
-            drawer.draw(&a1, &a2);
-        }
+            for &(a1, a2) in &lines {
+                drawer.draw(&a1, &a2);
+            }
 
-        // Grid
-        let mut grid_model = glm::translate(&glm::one(), &glm::vec3(0., 0., 0.));
-        grid_model *= glm::scaling(&glm::vec3(5., 5., 5.));
-
-        color_shader.bind();
-        color_shader.setMat4(&camera.projection, "projection");
-        color_shader.setMat4(&camera.view, "view");
-        color_shader.setMat4(&grid_model, "model");
-        color_shader.setVec4Float(&glm::vec4(1., 1., 1., 0.1), "color");
-        render_grid.draw_lines(2.);
-
-        drawer.draw_color(
-            &glm::vec3(0., 0.01, -5.),
-            &glm::vec3(0., 0.01, 5.),
-            &glm::vec4(1.0, 1.0, 1.0, 0.2),
-            0.5,
-        );
+            // Grid
+            let mut grid_model = glm::translate(&glm::one(), &glm::vec3(0., 0., 0.));
+            grid_model *= glm::scaling(&glm::vec3(5., 5., 5.));
 
-        drawer.draw_color(
-            &glm::vec3(-5., 0.01, 0.),
-            &glm::vec3(5., 0.01, 0.),
-            &glm::vec4(1.0, 1.0, 1.0, 0.2),
-            0.5,
-        );
+            color_shader.bind();
+            color_shader.setMat4(&grid_model, "model");
+            color_shader.setVec4Float(&glm::vec4(1., 1., 1., 0.1), "color");
+            render_grid.draw_lines(2.);
 
-        // 2. Clear main framebuffer
-        unsafe {
-            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
-            gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, fbo_postprocesing);
-            gl.BlitFramebuffer(
-                0,
-                0,
-                camera.screen_width as gl::types::GLint,
-                camera.screen_height as gl::types::GLint,
-                0,
-                0,
-                camera.screen_width as gl::types::GLint,
-                camera.screen_height as gl::types::GLint,
-                gl::COLOR_BUFFER_BIT,
-                gl::NEAREST,
+            color_shader.setMat4(&glm::identity(), "model");
+            color_shader.setVec4Float(
+                &glm::vec4(light_color[0], light_color[1], light_color[2], 1.),
+                "color",
             );
+            particles.draw(2.);
 
+            drawer.draw_color(
+                &glm::vec3(0., 0.01, -5.),
+                &glm::vec3(0., 0.01, 5.),
+                &glm::vec4(1.0, 1.0, 1.0, 0.2),
+                0.5,
+            );
+
+            drawer.draw_color(
+                &glm::vec3(-5., 0.01, 0.),
+                &glm::vec3(5., 0.01, 0.),
+                &glm::vec4(1.0, 1.0, 1.0, 0.2),
+                0.5,
+            );
+        }
+
+        unsafe {
+            gl.Disable(gl::SCISSOR_TEST);
+        }
+
+        // 2. Clear main framebuffer
+        scene_target.resolve_into(&resolve_target);
+
+        unsafe {
             gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
             gl.ClearColor(1., 1., 1., 1.);
             gl.Clear(gl::COLOR_BUFFER_BIT);
@@ -634,60 +915,128 @@ fn main() {
             gl.Disable(gl::DEPTH_TEST);
         }
 
+        Viewport::full(window_width, window_height).apply(&gl);
+
         screen_shader.bind();
         screen_shader.setVec3Float(
-            &glm::vec3(camera.screen_width as f32, camera.screen_height as f32, 0.),
+            &glm::vec3(window_width as f32, window_height as f32, 0.),
             "resolution",
         );
-        render_quad.draw_no_scaled(&screen_shader);
-
-        bold_font.render_with_shadow(
-            &camera,
-            "RUST ENGINE",
-            |width| (camera.screen_width as f32 - width - 30., 50.),
-            0.7,
-            &glm::vec3(1., 1., 1.),
-        );
+        render_quad.draw(&screen_shader);
+
+        // overlays are drawn per view, main view gets the HUD
+        for (index, view) in views.iter().enumerate() {
+            let render_camera = &view.camera;
+            frame_uniforms.update(&FrameData::new(render_camera, light));
+            render_camera.viewport().apply(&gl);
+
+            if !view.label.is_empty() {
+                normal_font.render_with_shadow(
+                    render_camera,
+                    view.label,
+                    |_| (10., render_camera.screen_height as f32 - 20.),
+                    0.45,
+                    &glm::vec3(1., 1., 1.),
+                );
+            }
 
-        normal_font.render_with_shadow(
-            &camera,
-            "BLOGOKODZIE.PL",
-            |width| (camera.screen_width as f32 - width - 30., 30.),
-            0.5,
-            &glm::vec3(1., 1., 1.),
-        );
+            let drawer = debug.setup_drawer(render_camera);
+            let test = render_camera.screen_to_world(&glm::vec3(
+                0.05 * render_camera.screen_width as f32,
+                0.05 * render_camera.screen_height as f32,
+                0.5,
+            ));
+            drawer.draw_gizmo(&test, 0.01, 0.5);
 
-        normal_font.render_with_shadow(
-            &camera,
-            format!(
-                "X: {:.2} Y: {:.2} Z: {:.2}",
-                &camera.position.x, &camera.position.y, &camera.position.z
-            )
-            .as_ref(),
-            |_| (90., 40.),
-            0.45,
-            &glm::vec3(1., 1., 1.),
-        );
-        normal_font.render_with_shadow(
-            &camera,
-            format!("{} FPS | {} UPDATES", frames_counter, updates_counter).as_ref(),
-            |_| (90., 20.),
-            0.45,
-            &glm::vec3(1., 1., 1.),
-        );
+            if index != 0 {
+                continue;
+            }
 
-        // gui
-        let test = glm::unproject(
-            &glm::vec3(0.05, 0.05, 0.5),
-            &camera.view,
-            &camera.projection,
-            glm::vec4(0., 0., 1., 1.),
-        );
-        drawer.draw_gizmo(&test, 0.01, 0.5);
-        gizmo.draw(&drawer, &camera);
+            bold_font.render_with_shadow(
+                render_camera,
+                "RUST ENGINE",
+                |width| (render_camera.screen_width as f32 - width - 30., 50.),
+                0.7,
+                &glm::vec3(1., 1., 1.),
+            );
+
+            normal_font.render_with_shadow(
+                render_camera,
+                "BLOGOKODZIE.PL",
+                |width| (render_camera.screen_width as f32 - width - 30., 30.),
+                0.5,
+                &glm::vec3(1., 1., 1.),
+            );
+
+            normal_font.render_with_shadow(
+                render_camera,
+                format!(
+                    "X: {:.2} Y: {:.2} Z: {:.2}",
+                    &render_camera.position.x, &render_camera.position.y, &render_camera.position.z
+                )
+                .as_ref(),
+                |_| (90., 40.),
+                0.45,
+                &glm::vec3(1., 1., 1.),
+            );
+            normal_font.render_with_shadow(
+                render_camera,
+                format!(
+                    "{} FPS | {} UPDATES | {} OVERLAPS",
+                    frames_counter, updates_counter, overlaps
+                )
+                .as_ref(),
+                |_| (90., 20.),
+                0.45,
+                &glm::vec3(1., 1., 1.),
+            );
+
+            gizmo.draw(&drawer, render_camera);
+        }
+
+        unsafe {
+            gl.Viewport(0, 0, window_width as i32, window_height as i32);
+        }
 
         // ************************* RENDERING **********************8**
 
+        // whole window including text, before the back buffer is swapped away
+        if take_screenshot {
+            take_screenshot = false;
+            let image = screenshot::read_screen(&gl, window_width, window_height);
+            match screenshot::save(&image, screenshot::SCREENSHOT_DIR) {
+                Ok(path) => println!("Screenshot saved to {}", path.display()),
+                Err(e) => println!("Cannot save screenshot: {}", e),
+            }
+        }
+
         window.gl_swap_window();
     }
 }
+
+// Layers of the block texture array, in block type order
+const BLOCK_TEXTURES: [&str; 3] = [
+    "res/wall.jpg",
+    "res/test/brickwall.jpg",
+    "res/Brick_Wall_018_SD/Brick_Wall_018_basecolor.jpg",
+];
+
+// Equirectangular hdr (a small procedural sky ships with the repo), or faces in
+// GL order: +X, -X, +Y, -Y, +Z, -Z
+const ENVIRONMENT_HDR: &str = "res/skybox/environment.hdr";
+const ENVIRONMENT_FACES: [&str; 6] = [
+    "res/skybox/px.jpg",
+    "res/skybox/nx.jpg",
+    "res/skybox/py.jpg",
+    "res/skybox/ny.jpg",
+    "res/skybox/pz.jpg",
+    "res/skybox/nz.jpg",
+];
+
+// Startup shaders are required, print the diagnostics instead of a panic
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|error| {
+        println!("{}", error);
+        std::process::exit(1);
+    })
+}
@@ -0,0 +1,112 @@
+use crate::compute::{dispatch, group_count, memory_barrier, StorageBuffer};
+use crate::shader::Program;
+use gl;
+
+// Matches `Particle` in particles.comp, std430
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Particle {
+    pub position: [f32; 4], // w is remaining life
+    pub velocity: [f32; 4],
+}
+
+const LOCAL_SIZE: u32 = 128;
+
+/**
+    Particles simulated by a compute shader, the storage buffer is drawn
+    directly as a vertex buffer of points
+**/
+pub struct ParticleSystem {
+    gl: gl::GlPtr,
+    program: Program,
+    buffer: StorageBuffer<Particle>,
+    vao: gl::types::GLuint,
+
+    pub emitter: glm::Vec3,
+    time: f32,
+}
+
+impl ParticleSystem {
+    pub fn new(gl: &gl::GlPtr, program: Program, count: usize) -> Self {
+        // dead particles respawn on the first update
+        let particles = vec![
+            Particle {
+                position: [0., 0., 0., 0.],
+                velocity: [0., 0., 0., 0.],
+            };
+            count
+        ];
+        let buffer = StorageBuffer::new(gl, &particles);
+        let mut vao = 0;
+
+        unsafe {
+            gl.GenVertexArrays(1, &mut vao);
+            gl.BindVertexArray(vao);
+            gl.BindBuffer(gl::ARRAY_BUFFER, buffer.id);
+
+            // position only, as vec3 at location 0 like other models
+            gl.EnableVertexAttribArray(0);
+            gl.VertexAttribPointer(
+                0,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                std::mem::size_of::<Particle>() as gl::types::GLint,
+                std::ptr::null(),
+            );
+
+            gl.BindVertexArray(0);
+            gl.BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        Self {
+            gl: gl.clone(),
+            program,
+            buffer,
+            vao,
+            emitter: glm::vec3(0., 0., 0.),
+            time: 0.,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+
+        self.program.bind();
+        self.program
+            .set_uniform("count", &(self.buffer.len() as u32));
+        self.program.set_uniform("dt", &dt);
+        self.program.set_uniform("time", &self.time);
+        self.program.set_uniform("emitter", &self.emitter);
+
+        self.buffer.bind_base(0);
+        dispatch(
+            &self.gl,
+            &self.program,
+            group_count(self.buffer.len(), LOCAL_SIZE),
+            1,
+            1,
+        );
+
+        // written positions are read as vertices
+        memory_barrier(&self.gl, gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    }
+
+    // With a shader already bound, e.g. color shader
+    pub fn draw(&self, point_size: f32) {
+        unsafe {
+            self.gl.PointSize(point_size);
+            self.gl.BindVertexArray(self.vao);
+            self.gl.DrawArrays(gl::POINTS, 0, self.buffer.len() as i32);
+            self.gl.BindVertexArray(0);
+        }
+    }
+}
+
+impl Drop for ParticleSystem {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Snippets that can be included, embedded for release builds. Paths are relative to src/
+static EMBEDDED: &[(&str, &str)] = &[
+    (
+        "shaders/common/frame.glsl",
+        include_str!("shaders/common/frame.glsl"),
+    ),
+    (
+        "shaders/common/light.glsl",
+        include_str!("shaders/common/light.glsl"),
+    ),
+    (
+        "shaders/common/parallax.glsl",
+        include_str!("shaders/common/parallax.glsl"),
+    ),
+];
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/**
+    Output of the preprocessor. Every file gets a `#line` source number, its index
+    in `files` and `sources`, so compiler logs can be mapped back with
+    `ShaderError::locate`.
+**/
+pub struct Preprocessed {
+    pub source: String,
+    pub files: Vec<String>,
+    pub sources: Vec<String>,
+}
+
+// Disk in debug builds so includes can be hot reloaded, embedded copy otherwise
+pub fn load(path: &str) -> Option<String> {
+    let from_disk = if cfg!(debug_assertions) {
+        fs::read_to_string(path).ok()
+    } else {
+        None
+    };
+
+    from_disk.or_else(|| {
+        let relative = path.strip_prefix("src/").unwrap_or(path);
+        EMBEDDED
+            .iter()
+            .find(|&&(embedded_path, _)| embedded_path == relative)
+            .map(|&(_, source)| source.to_string())
+    })
+}
+
+/**
+    Resolves `#include "file"` relative to the including file, every file is included
+    once. `defines` are "NAME" or "NAME VALUE" and go right after `#version`.
+**/
+pub fn preprocess(path: &str, source: &str, defines: &[String]) -> Result<Preprocessed, String> {
+    let mut preprocessed = Preprocessed {
+        source: String::new(),
+        files: vec![],
+        sources: vec![],
+    };
+
+    let mut body = String::new();
+    let mut version = None;
+
+    include(&mut preprocessed, &mut body, &mut version, path, source, 0)?;
+
+    let version = version.unwrap_or_else(|| "#version 330 core".to_string());
+    preprocessed.source = format!("{}\n", version);
+
+    for define in defines {
+        preprocessed.source += &format!("#define {}\n", define);
+    }
+
+    preprocessed.source += &body;
+    Ok(preprocessed)
+}
+
+fn include(
+    preprocessed: &mut Preprocessed,
+    body: &mut String,
+    version: &mut Option<String>,
+    path: &str,
+    source: &str,
+    depth: usize,
+) -> Result<(), String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes nested too deep", path));
+    }
+
+    let file_index = preprocessed.files.len();
+    preprocessed.files.push(path.to_string());
+    preprocessed.sources.push(source.to_string());
+    *body += &format!("#line 1 {}\n", file_index);
+
+    for (number, line) in source.lines().enumerate() {
+        let directive = line.trim_start();
+
+        if directive.starts_with("#version") {
+            if version.is_none() {
+                *version = Some(directive.to_string());
+            }
+
+            // keep line numbers
+            body.push('\n');
+        } else if let Some(included) = directive.strip_prefix("#include") {
+            let included = included.trim().trim_matches('"');
+            let included_path = resolve(path, included);
+
+            if !preprocessed.files.contains(&included_path) {
+                let included_source = load(&included_path).ok_or_else(|| {
+                    format!("{}:{}: cannot include {}", path, number + 1, included_path)
+                })?;
+
+                include(
+                    preprocessed,
+                    body,
+                    version,
+                    &included_path,
+                    &included_source,
+                    depth + 1,
+                )?;
+            }
+
+            // back to this file, after the include
+            *body += &format!("#line {} {}\n", number + 2, file_index);
+        } else {
+            *body += line;
+            body.push('\n');
+        }
+    }
+
+    Ok(())
+}
+
+// `included` relative to directory of `path`, without `..`
+fn resolve(path: &str, included: &str) -> String {
+    let joined = Path::new(path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(included);
+
+    let mut resolved = PathBuf::new();
+
+    for component in joined.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+
+    resolved.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `files` under a fresh temporary directory, returns the path of the first one
+    fn write_tree(name: &str, files: &[(&str, &str)]) -> String {
+        let root =
+            std::env::temp_dir().join(format!("preprocessor_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for &(path, source) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        root.join(files[0].0).to_string_lossy().replace('\\', "/")
+    }
+
+    fn run(path: &str, defines: &[&str]) -> Result<Preprocessed, String> {
+        let source = fs::read_to_string(path).unwrap();
+        let defines: Vec<String> = defines.iter().map(|d| d.to_string()).collect();
+        preprocess(path, &source, &defines)
+    }
+
+    #[test]
+    fn resolves_parent_directories() {
+        assert_eq!(
+            resolve("src/shaders/basic/basic.frag", "../common/frame.glsl"),
+            "src/shaders/common/frame.glsl"
+        );
+        assert_eq!(resolve("a/b/c.glsl", "./../../d.glsl"), "d.glsl");
+
+        let main = write_tree(
+            "parent",
+            &[
+                (
+                    "shaders/basic/main.frag",
+                    "#include \"../common/light.glsl\"\n",
+                ),
+                ("shaders/common/light.glsl", "float light;\n"),
+            ],
+        );
+        let preprocessed = run(&main, &[]).unwrap();
+
+        assert_eq!(preprocessed.files.len(), 2);
+        assert!(preprocessed.files[1].ends_with("shaders/common/light.glsl"));
+        assert!(!preprocessed.files[1].contains(".."));
+        assert!(preprocessed.source.contains("float light;"));
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        let main = write_tree(
+            "once",
+            &[
+                (
+                    "main.frag",
+                    "#include \"a.glsl\"\n#include \"b.glsl\"\n#include \"a.glsl\"\n",
+                ),
+                ("a.glsl", "#include \"common.glsl\"\nfloat a;\n"),
+                ("b.glsl", "#include \"common.glsl\"\nfloat b;\n"),
+                ("common.glsl", "float common;\n"),
+            ],
+        );
+        let preprocessed = run(&main, &[]).unwrap();
+
+        let names: Vec<&str> = preprocessed
+            .files
+            .iter()
+            .map(|file| file.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(names, ["main.frag", "a.glsl", "common.glsl", "b.glsl"]);
+        assert_eq!(preprocessed.sources[2], "float common;\n");
+        assert_eq!(preprocessed.source.matches("float common;").count(), 1);
+        assert_eq!(preprocessed.source.matches("float a;").count(), 1);
+    }
+
+    #[test]
+    fn limits_include_depth() {
+        // 0.glsl includes 1.glsl includes 2.glsl...
+        let chain: Vec<(String, String)> = (0..=MAX_INCLUDE_DEPTH + 1)
+            .map(|i| {
+                (
+                    format!("{}.glsl", i),
+                    format!("#include \"{}.glsl\"\n", i + 1),
+                )
+            })
+            .chain(std::iter::once((
+                format!("{}.glsl", MAX_INCLUDE_DEPTH + 2),
+                "float end;\n".to_string(),
+            )))
+            .collect();
+        let files: Vec<(&str, &str)> = chain
+            .iter()
+            .map(|(p, s)| (p.as_str(), s.as_str()))
+            .collect();
+
+        let first = write_tree("depth", &files);
+        let error = run(&first, &[]).err().unwrap();
+        assert!(error.ends_with("includes nested too deep"), "{}", error);
+        assert!(
+            error.contains(&format!("{}.glsl", MAX_INCLUDE_DEPTH + 1)),
+            "{}",
+            error
+        );
+
+        // last file exactly at MAX_INCLUDE_DEPTH is fine
+        let start = write_tree("depth_ok", &files[2..]);
+        assert!(run(&start, &[]).is_ok());
+    }
+
+    #[test]
+    fn line_directives_return_to_the_includer() {
+        let main = write_tree(
+            "lines",
+            &[
+                (
+                    "main.frag",
+                    "#version 420 core\n#include \"a.glsl\"\nvoid main() {}\n",
+                ),
+                ("a.glsl", "float a;\nfloat b;\n"),
+            ],
+        );
+        let preprocessed = run(&main, &[]).unwrap();
+
+        assert_eq!(
+            preprocessed.source,
+            "#version 420 core\n\
+             #line 1 0\n\
+             \n\
+             #line 1 1\n\
+             float a;\n\
+             float b;\n\
+             #line 3 0\n\
+             void main() {}\n"
+        );
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let main = write_tree(
+            "defines",
+            &[(
+                "main.frag",
+                "// comment first\n#version 450 core\nvoid main() {}\n",
+            )],
+        );
+        let preprocessed = run(&main, &["NORMAL_MAP", "LIGHTS 4"]).unwrap();
+
+        assert!(preprocessed.source.starts_with(
+            "#version 450 core\n#define NORMAL_MAP\n#define LIGHTS 4\n#line 1 0\n// comment first\n\n"
+        ));
+
+        // no #version anywhere falls back to 330
+        let bare = write_tree("bare", &[("main.frag", "void main() {}\n")]);
+        let preprocessed = run(&bare, &["A"]).unwrap();
+        assert!(preprocessed
+            .source
+            .starts_with("#version 330 core\n#define A\n#line 1 0\n"));
+    }
+}
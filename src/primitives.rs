@@ -1,7 +1,10 @@
 extern crate nalgebra_glm as glm;
+use crate::reflection::{components, ProgramReflection};
+use crate::sampler::Sampler;
 use crate::shader::{Program, Shader};
 use crate::texture;
 use crate::texture::{Texture, TextureKind};
+use crate::uniform;
 use gl;
 use itertools::{zip_eq, Itertools};
 use std::borrow::Borrow;
@@ -85,7 +88,8 @@ fn scale_uv(verts: &Vec<f32>, width: f32, height: f32) -> Vec<f32> {
         .collect()
 }
 
-pub type TextureAttachment<'a> = (&'a Texture, TextureKind);
+// Sampler state belongs to the binding, the same texture can be sampled differently
+pub type TextureAttachment<'a> = (&'a Texture, TextureKind, &'a Sampler);
 
 pub struct Model<'a> {
     gl: gl::GlPtr,
@@ -96,6 +100,7 @@ pub struct Model<'a> {
 
     triangles: i32,
     textures: Vec<TextureAttachment<'a>>,
+    locations: Vec<i32>, // floats per attribute location
 }
 
 fn setup_vertex_attrib(gl: &gl::GlPtr, locations: &[i32]) {
@@ -124,6 +129,34 @@ fn setup_vertex_attrib(gl: &gl::GlPtr, locations: &[i32]) {
     }
 }
 
+// Every attribute the program reads has to be provided with the same size
+pub fn validate_vertex_attrib(
+    locations: &[i32],
+    reflection: &ProgramReflection,
+) -> Result<(), String> {
+    for attribute in reflection.attributes.iter().filter(|a| a.location >= 0) {
+        let expected = components(attribute.kind);
+
+        match locations.get(attribute.location as usize) {
+            None => {
+                return Err(format!(
+                    "attribute {} (location {}) is not provided",
+                    attribute.name, attribute.location
+                ))
+            }
+            Some(&len) if len != expected => {
+                return Err(format!(
+                    "attribute {} (location {}) expects {} floats, got {}",
+                    attribute.name, attribute.location, expected, len
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
 /* https://www.khronos.org/opengl/wiki/Vertex_Specification#Vertex_Buffer_Object */
 fn setup_vbo(gl: &gl::GlPtr, vertices: &Vec<f32>, locations: &[i32]) -> GlInt {
     let mut vbo: GlInt = 0;
@@ -216,6 +249,7 @@ pub fn create<'a>(
         ebo: 0,
         triangles,
         textures,
+        locations: locations.to_vec(),
     }
 }
 
@@ -244,10 +278,57 @@ pub fn create_with_indices<'a>(
         ebo,
         triangles,
         textures,
+        locations: locations.to_vec(),
     }
 }
 
 impl Model<'_> {
+    // Vertex layout and texture targets against what the program declares
+    pub fn validate(&self, shader: &Program) -> Result<(), String> {
+        validate_vertex_attrib(&self.locations, shader.reflection())?;
+
+        for (name, &(texture, _, _)) in self.sampler_names().iter().zip(&self.textures) {
+            let uniform = match shader.reflection().uniform(name) {
+                Some(uniform) => uniform,
+                None => continue,
+            };
+
+            if uniform::sampler_target(uniform.kind) != Some(texture.target) {
+                return Err(format!(
+                    "{} is {}, texture has another target",
+                    name,
+                    uniform::type_name(uniform.kind)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // material.texture_diffuse1, material.texture_diffuse2... in texture order
+    fn sampler_names(&self) -> Vec<String> {
+        let mut diffuse_number = 1;
+        let mut specular_number = 1;
+        let mut normal_number = 1;
+        let mut height_number = 1;
+
+        self.textures
+            .iter()
+            .map(|&(_, kind, _)| {
+                let number = match kind {
+                    TextureKind::Diffuse => &mut diffuse_number,
+                    TextureKind::Specular => &mut specular_number,
+                    TextureKind::Normal => &mut normal_number,
+                    TextureKind::Height => &mut height_number,
+                };
+
+                let name = format!("material.{}{}", kind.as_str(), number);
+                *number += 1;
+                name
+            })
+            .collect()
+    }
+
     pub fn raw_draw(&self, mode: gl::types::GLenum) {
         unsafe {
             self.gl.BindVertexArray(self.vao);
@@ -264,13 +345,7 @@ impl Model<'_> {
     }
 
     pub fn draw(&self, shader: &Program) {
-        self.bind_textures_to(&shader, true);
-        self.raw_draw(gl::TRIANGLES);
-        self.unbind_textures_from(&shader);
-    }
-
-    pub fn draw_no_scaled(&self, shader: &Program) {
-        self.bind_textures_to(&shader, false);
+        self.bind_textures_to(&shader);
         self.raw_draw(gl::TRIANGLES);
         self.unbind_textures_from(&shader);
     }
@@ -332,75 +407,31 @@ impl Model<'_> {
         }
     }
 
-    pub fn bind_textures_to(&self, shader: &Program, mipmap: bool) {
-        let mut diffuse_number = 1;
-        let mut specular_number = 1;
-        let mut normal_number = 1;
-        let mut height_number = 1;
+    // Each texture to its own target, arrays and cubemaps included
+    pub fn bind_textures_to(&self, shader: &Program) {
+        let names = self.sampler_names();
 
-        for (i, &(texture, kind)) in self.textures.iter().enumerate() {
+        for (i, (&(texture, _, sampler), name)) in self.textures.iter().zip(&names).enumerate() {
             unsafe {
                 self.gl.ActiveTexture(gl::TEXTURE0 + i as u32);
+            }
 
-                let number = match kind {
-                    TextureKind::Diffuse => &mut diffuse_number,
-                    TextureKind::Specular => &mut specular_number,
-                    TextureKind::Normal => &mut normal_number,
-                    TextureKind::Height => &mut height_number,
-                };
-
-                shader.setInt(i as i32, &format!("material.{}{}", kind.as_str(), number));
-                texture.bind();
-
-                unsafe {
-                    self.gl
-                        .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-                    self.gl
-                        .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-
-                    // self.gl.TexParameteri(
-                    //     gl::TEXTURE_2D,
-                    //     gl::TEXTURE_WRAP_S,
-                    //     gl::CLAMP_TO_EDGE as gl::types::GLint,
-                    // );
-                    // self.gl.TexParameteri(
-                    //     gl::TEXTURE_2D,
-                    //     gl::TEXTURE_WRAP_T,
-                    //     gl::CLAMP_TO_EDGE as gl::types::GLint,
-                    // );
-
-                    if mipmap {
-                        self.gl.TexParameteri(
-                            gl::TEXTURE_2D,
-                            gl::TEXTURE_MIN_FILTER,
-                            gl::LINEAR_MIPMAP_NEAREST as gl::types::GLint,
-                        );
-                    } else {
-                        self.gl.TexParameteri(
-                            gl::TEXTURE_2D,
-                            gl::TEXTURE_MIN_FILTER,
-                            gl::LINEAR as gl::types::GLint,
-                        );
-                    }
-
-                    self.gl.TexParameteri(
-                        gl::TEXTURE_2D,
-                        gl::TEXTURE_MAG_FILTER,
-                        gl::LINEAR as gl::types::GLint,
-                    );
-                }
-
-                *number += 1;
+            // only samplers the program declares, e.g. screen shader has no normal map
+            if shader.reflection().has_sampler(name) {
+                shader.set_uniform(name, &uniform::Sampler(i as i32));
             }
+            texture.bind();
+            sampler.bind(i as u32);
         }
     }
 
     fn unbind_textures_from(&self, shader: &Program) {
-        for (i, texture) in self.textures.iter().enumerate() {
+        for (i, &(texture, _, _)) in self.textures.iter().enumerate() {
             unsafe {
                 self.gl.ActiveTexture(gl::TEXTURE0 + i as u32);
-                self.gl.BindTexture(gl::TEXTURE_2D, 0);
+                self.gl.BindTexture(texture.target, 0);
             }
+            Sampler::unbind(&self.gl, i as u32);
         }
     }
 }
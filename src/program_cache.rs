@@ -0,0 +1,281 @@
+use gl;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Relative to the working directory, like res/
+const CACHE_DIR: &str = "cache/shaders";
+
+thread_local! {
+    // GL calls all happen on the main thread, so this is the only copy that matters
+    static DIR: RefCell<PathBuf> = RefCell::new(PathBuf::from(CACHE_DIR));
+}
+
+pub fn dir() -> PathBuf {
+    DIR.with(|dir| dir.borrow().clone())
+}
+
+// Redirects the cache, returns the previous directory
+pub fn set_dir<P: AsRef<Path>>(path: P) -> PathBuf {
+    DIR.with(|dir| dir.replace(path.as_ref().to_path_buf()))
+}
+
+/**
+    Linked program binaries on disk, keyed by a hash of the preprocessed sources
+    and the driver strings. A binary the driver rejects is deleted and the caller
+    compiles from source instead.
+**/
+
+fn driver_string(gl: &gl::GlPtr, name: gl::types::GLenum) -> String {
+    unsafe {
+        let value = gl.GetString(name);
+
+        if value.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(value as *const _)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+// FNV-1a, stable between runs and compiler versions unlike DefaultHasher
+fn hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn key(gl: &gl::GlPtr, sources: &[(gl::types::GLenum, &str)]) -> u64 {
+    let mut key = 0xcbf29ce484222325;
+
+    for &name in &[gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        key = hash(key, driver_string(gl, name).as_bytes());
+    }
+
+    for &(kind, source) in sources {
+        key = hash(key, &kind.to_le_bytes());
+        key = hash(key, source.as_bytes());
+    }
+
+    key
+}
+
+fn path(key: u64) -> PathBuf {
+    dir().join(format!("{:016x}.bin", key))
+}
+
+fn binary_formats(gl: &gl::GlPtr) -> Vec<gl::types::GLenum> {
+    let mut count = 0;
+
+    unsafe {
+        gl.GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut count);
+    }
+
+    let mut formats = vec![0; count.max(0) as usize];
+
+    if !formats.is_empty() {
+        unsafe {
+            gl.GetIntegerv(gl::PROGRAM_BINARY_FORMATS, formats.as_mut_ptr());
+        }
+    }
+
+    formats
+        .into_iter()
+        .map(|format| format as gl::types::GLenum)
+        .collect()
+}
+
+pub fn is_supported(gl: &gl::GlPtr) -> bool {
+    !binary_formats(gl).is_empty()
+}
+
+// Linked program from the cache, None when missing or rejected by the driver
+pub fn load(gl: &gl::GlPtr, key: u64) -> Option<gl::types::GLuint> {
+    let data = fs::read(path(key)).ok()?;
+
+    if data.len() < 4 {
+        return None;
+    }
+
+    let mut format = [0u8; 4];
+    format.copy_from_slice(&data[..4]);
+    let format = u32::from_le_bytes(format);
+    let binary = &data[4..];
+
+    // ProgramBinary raises INVALID_ENUM for a format the driver doesn't know
+    if !binary_formats(gl).contains(&format) {
+        let _ = fs::remove_file(path(key));
+        return None;
+    }
+
+    let mut success = 0;
+    let program_id = unsafe {
+        let program_id = gl.CreateProgram();
+        gl.ProgramBinary(
+            program_id,
+            format,
+            binary.as_ptr() as *const gl::types::GLvoid,
+            binary.len() as gl::types::GLsizei,
+        );
+        gl.GetProgramiv(program_id, gl::LINK_STATUS, &mut success);
+        program_id
+    };
+
+    if success == 0 {
+        // e.g. driver update, compiled again and stored by the caller
+        unsafe { gl.DeleteProgram(program_id) }
+        let _ = fs::remove_file(path(key));
+        return None;
+    }
+
+    Some(program_id)
+}
+
+// Program has to be linked with PROGRAM_BINARY_RETRIEVABLE_HINT
+pub fn store(gl: &gl::GlPtr, key: u64, program_id: gl::types::GLuint) -> std::io::Result<()> {
+    if !is_supported(gl) {
+        return Ok(());
+    }
+
+    let mut length = 0;
+    unsafe {
+        gl.GetProgramiv(program_id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+    }
+
+    if length <= 0 {
+        return Ok(());
+    }
+
+    let mut binary = vec![0u8; length as usize];
+    let mut written = 0;
+    let mut format = 0;
+
+    unsafe {
+        gl.GetProgramBinary(
+            program_id,
+            length,
+            &mut written,
+            &mut format,
+            binary.as_mut_ptr() as *mut gl::types::GLvoid,
+        );
+    }
+
+    binary.truncate(written.max(0) as usize);
+
+    let mut data = format.to_le_bytes().to_vec();
+    data.extend(binary);
+
+    fs::create_dir_all(dir())?;
+    fs::write(path(key), data)
+}
+
+pub fn clear() -> std::io::Result<()> {
+    match fs::remove_dir_all(dir()) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/**
+    SELF TEST
+**/
+
+// Store, load, and fall back on a corrupted binary, `--test-program-cache` runs it
+// (LIBGL_ALWAYS_SOFTWARE=1 for Mesa llvmpipe). Uses a temporary directory, the
+// real cache is left alone
+pub fn self_test(gl: &gl::GlPtr) -> Result<(), String> {
+    if !is_supported(gl) {
+        return Err("driver has no program binary formats".to_string());
+    }
+
+    let previous =
+        set_dir(std::env::temp_dir().join(format!("program_cache_test_{}", std::process::id())));
+
+    let result = run_self_test(gl);
+    let cleanup = clear();
+    set_dir(previous);
+
+    result?;
+    cleanup.map_err(|e| e.to_string())
+}
+
+fn run_self_test(gl: &gl::GlPtr) -> Result<(), String> {
+    use crate::shader::Program;
+
+    let sources = || {
+        vec![shader_source!(
+            gl::COMPUTE_SHADER,
+            "shaders/compute/double.comp"
+        )]
+    };
+
+    clear().map_err(|e| e.to_string())?;
+
+    // 1. compiled and stored
+    let compiled = Program::from_sources(gl, sources())?;
+    let files: Vec<PathBuf> = fs::read_dir(dir())
+        .map_err(|e| format!("cache was not written: {}", e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+
+    if files.len() != 1 {
+        return Err(format!("expected 1 cached binary, found {}", files.len()));
+    }
+
+    // 2. loaded, same uniforms as compiled
+    let key = files[0]
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+        .ok_or_else(|| format!("unexpected cache file {}", files[0].display()))?;
+
+    match load(gl, key) {
+        Some(program_id) => unsafe { gl.DeleteProgram(program_id) },
+        None => return Err("stored binary was not loaded".to_string()),
+    }
+
+    let cached = Program::from_sources(gl, sources())?;
+
+    if cached.reflection().uniforms.len() != compiled.reflection().uniforms.len() {
+        return Err("cached program differs from compiled one".to_string());
+    }
+
+    // 3. unknown format and garbage in a known format are rejected, errors from
+    // before load are still there afterwards and nothing is added
+    let stored = fs::read(&files[0]).map_err(|e| e.to_string())?;
+    let mut garbage = stored[..4].to_vec();
+    garbage.extend_from_slice(&[0u8; 60]);
+
+    for corrupted in &[vec![0u8; 64], garbage] {
+        fs::write(&files[0], corrupted).map_err(|e| e.to_string())?;
+
+        unsafe {
+            gl.BindBuffer(0, 0); // INVALID_ENUM on purpose
+        }
+
+        if load(gl, key).is_some() {
+            return Err("corrupted binary was loaded".to_string());
+        }
+
+        let errors = unsafe { [gl.GetError(), gl.GetError()] };
+        if errors != [gl::INVALID_ENUM, gl::NO_ERROR] {
+            return Err(format!(
+                "GL errors around a rejected binary: {:#x}, {:#x}",
+                errors[0], errors[1]
+            ));
+        }
+    }
+
+    fs::write(&files[0], [0u8; 64]).map_err(|e| e.to_string())?;
+    Program::from_sources(gl, sources())?;
+
+    let replaced = fs::read(&files[0]).map_err(|e| e.to_string())?;
+    if replaced == [0u8; 64] {
+        return Err("corrupted binary was not replaced".to_string());
+    }
+
+    Ok(())
+}
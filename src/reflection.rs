@@ -0,0 +1,269 @@
+use crate::uniform::is_sampler;
+use gl;
+use gl::types::{GLenum, GLint, GLuint};
+
+#[derive(Clone, Debug)]
+pub struct ActiveAttribute {
+    pub name: String,
+    pub location: GLint, // -1 for built-ins like gl_VertexID
+    pub kind: GLenum,
+    pub size: GLint,
+}
+
+#[derive(Clone, Debug)]
+pub struct ActiveUniform {
+    pub name: String,
+    pub location: GLint, // -1 for members of blocks
+    pub kind: GLenum,
+    pub size: GLint,
+    pub block_index: GLint, // -1 outside of blocks
+}
+
+#[derive(Clone, Debug)]
+pub struct ActiveBlock {
+    pub name: String,
+    pub index: GLuint,
+    pub binding: GLint,
+    pub data_size: GLint, // bytes
+    pub members: Vec<String>,
+}
+
+/**
+    What a linked program consumes, queried once with the program interface API
+**/
+#[derive(Clone, Debug, Default)]
+pub struct ProgramReflection {
+    pub attributes: Vec<ActiveAttribute>,
+    pub uniforms: Vec<ActiveUniform>,
+    pub uniform_blocks: Vec<ActiveBlock>,
+    pub storage_blocks: Vec<ActiveBlock>,
+}
+
+impl ProgramReflection {
+    pub fn new(gl: &gl::GlPtr, program_id: GLuint) -> Self {
+        let attributes = (0..resource_count(gl, program_id, gl::PROGRAM_INPUT))
+            .map(|index| {
+                let values = resource_values(
+                    gl,
+                    program_id,
+                    gl::PROGRAM_INPUT,
+                    index,
+                    &[gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION],
+                );
+
+                ActiveAttribute {
+                    name: resource_name(gl, program_id, gl::PROGRAM_INPUT, index),
+                    kind: values[0] as GLenum,
+                    size: values[1],
+                    location: values[2],
+                }
+            })
+            .collect();
+
+        let uniforms: Vec<ActiveUniform> = (0..resource_count(gl, program_id, gl::UNIFORM))
+            .map(|index| {
+                let values = resource_values(
+                    gl,
+                    program_id,
+                    gl::UNIFORM,
+                    index,
+                    &[gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION, gl::BLOCK_INDEX],
+                );
+
+                ActiveUniform {
+                    name: resource_name(gl, program_id, gl::UNIFORM, index),
+                    kind: values[0] as GLenum,
+                    size: values[1],
+                    location: values[2],
+                    block_index: values[3],
+                }
+            })
+            .collect();
+
+        let uniform_blocks = blocks(gl, program_id, gl::UNIFORM_BLOCK, gl::UNIFORM);
+        let storage_blocks = blocks(
+            gl,
+            program_id,
+            gl::SHADER_STORAGE_BLOCK,
+            gl::BUFFER_VARIABLE,
+        );
+
+        Self {
+            attributes,
+            uniforms,
+            uniform_blocks,
+            storage_blocks,
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&ActiveAttribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+
+    // Plain name of an array matches its first element
+    pub fn uniform(&self, name: &str) -> Option<&ActiveUniform> {
+        self.uniforms
+            .iter()
+            .find(|u| u.name == name || u.name.strip_suffix("[0]") == Some(name))
+    }
+
+    pub fn samplers(&self) -> impl Iterator<Item = &ActiveUniform> {
+        self.uniforms.iter().filter(|u| is_sampler(u.kind))
+    }
+
+    pub fn has_sampler(&self, name: &str) -> bool {
+        self.uniform(name).map_or(false, |u| is_sampler(u.kind))
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&ActiveBlock> {
+        self.uniform_blocks.iter().find(|b| b.name == name)
+    }
+
+    pub fn storage_block(&self, name: &str) -> Option<&ActiveBlock> {
+        self.storage_blocks.iter().find(|b| b.name == name)
+    }
+}
+
+// Floats per vertex for an attribute type, 0 for types models can not provide
+pub fn components(kind: GLenum) -> i32 {
+    match kind {
+        gl::FLOAT => 1,
+        gl::FLOAT_VEC2 => 2,
+        gl::FLOAT_VEC3 => 3,
+        gl::FLOAT_VEC4 => 4,
+        _ => 0,
+    }
+}
+
+/**
+    QUERIES
+**/
+
+fn resource_count(gl: &gl::GlPtr, program_id: GLuint, interface: GLenum) -> GLuint {
+    let mut count: GLint = 0;
+
+    unsafe {
+        gl.GetProgramInterfaceiv(program_id, interface, gl::ACTIVE_RESOURCES, &mut count);
+    }
+
+    count.max(0) as GLuint
+}
+
+fn resource_name(gl: &gl::GlPtr, program_id: GLuint, interface: GLenum, index: GLuint) -> String {
+    let mut max_length: GLint = 0;
+    let mut length: GLint = 0;
+
+    unsafe {
+        gl.GetProgramInterfaceiv(program_id, interface, gl::MAX_NAME_LENGTH, &mut max_length);
+    }
+
+    let mut buffer = vec![0u8; max_length.max(1) as usize];
+
+    unsafe {
+        gl.GetProgramResourceName(
+            program_id,
+            interface,
+            index,
+            buffer.len() as GLint,
+            &mut length,
+            buffer.as_mut_ptr() as *mut gl::types::GLchar,
+        );
+    }
+
+    buffer.truncate(length.max(0) as usize);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+fn resource_values(
+    gl: &gl::GlPtr,
+    program_id: GLuint,
+    interface: GLenum,
+    index: GLuint,
+    properties: &[GLenum],
+) -> Vec<GLint> {
+    let mut values = vec![0; properties.len()];
+
+    unsafe {
+        gl.GetProgramResourceiv(
+            program_id,
+            interface,
+            index,
+            properties.len() as GLint,
+            properties.as_ptr(),
+            values.len() as GLint,
+            std::ptr::null_mut(),
+            values.as_mut_ptr(),
+        );
+    }
+
+    values
+}
+
+// `member_interface` is where the ACTIVE_VARIABLES indices point to
+fn blocks(
+    gl: &gl::GlPtr,
+    program_id: GLuint,
+    interface: GLenum,
+    member_interface: GLenum,
+) -> Vec<ActiveBlock> {
+    (0..resource_count(gl, program_id, interface))
+        .map(|index| {
+            let values = resource_values(
+                gl,
+                program_id,
+                interface,
+                index,
+                &[
+                    gl::BUFFER_BINDING,
+                    gl::BUFFER_DATA_SIZE,
+                    gl::NUM_ACTIVE_VARIABLES,
+                ],
+            );
+
+            let member_indices = if values[2] > 0 {
+                resource_values_n(gl, program_id, interface, index, values[2] as usize)
+            } else {
+                vec![]
+            };
+
+            ActiveBlock {
+                name: resource_name(gl, program_id, interface, index),
+                index,
+                binding: values[0],
+                data_size: values[1],
+                members: member_indices
+                    .iter()
+                    .map(|&member| {
+                        resource_name(gl, program_id, member_interface, member as GLuint)
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// ACTIVE_VARIABLES returns one value per member
+fn resource_values_n(
+    gl: &gl::GlPtr,
+    program_id: GLuint,
+    interface: GLenum,
+    index: GLuint,
+    count: usize,
+) -> Vec<GLint> {
+    let mut values = vec![0; count];
+
+    unsafe {
+        gl.GetProgramResourceiv(
+            program_id,
+            interface,
+            index,
+            1,
+            &gl::ACTIVE_VARIABLES,
+            values.len() as GLint,
+            std::ptr::null_mut(),
+            values.as_mut_ptr(),
+        );
+    }
+
+    values
+}
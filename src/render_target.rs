@@ -0,0 +1,386 @@
+use crate::texture::Texture;
+use gl;
+use std::cell::Cell;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    Incomplete(gl::types::GLenum),
+    TooManySamples { requested: u32, max: u32 },
+    TooManyAttachments { requested: usize, max: usize },
+}
+
+impl fmt::Display for FramebufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FramebufferError::Incomplete(status) => {
+                write!(f, "framebuffer is not complete: {}", status_name(*status))
+            }
+            FramebufferError::TooManySamples { requested, max } => write!(
+                f,
+                "{} samples requested, the driver supports {}",
+                requested, max
+            ),
+            FramebufferError::TooManyAttachments { requested, max } => write!(
+                f,
+                "{} color attachments requested, the driver supports {}",
+                requested, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FramebufferError {}
+
+impl From<FramebufferError> for String {
+    fn from(error: FramebufferError) -> String {
+        error.to_string()
+    }
+}
+
+fn status_name(status: gl::types::GLenum) -> &'static str {
+    match status {
+        gl::FRAMEBUFFER_UNDEFINED => "FRAMEBUFFER_UNDEFINED",
+        gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "FRAMEBUFFER_INCOMPLETE_ATTACHMENT",
+        gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => {
+            "FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT"
+        }
+        gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => "FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER",
+        gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => "FRAMEBUFFER_INCOMPLETE_READ_BUFFER",
+        gl::FRAMEBUFFER_UNSUPPORTED => "FRAMEBUFFER_UNSUPPORTED",
+        gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "FRAMEBUFFER_INCOMPLETE_MULTISAMPLE",
+        gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => "FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS",
+        _ => "unknown status",
+    }
+}
+
+/**
+    ATTACHMENT DESCRIPTION
+    RenderTargetOptions::new().color(gl::RGBA16F).depth_stencil(gl::DEPTH24_STENCIL8).samples(4)
+**/
+
+#[derive(Clone, Debug)]
+pub struct RenderTargetOptions {
+    pub colors: Vec<gl::types::GLenum>, // internal formats, in COLOR_ATTACHMENTi order
+    pub depth_stencil: Option<gl::types::GLenum>,
+    pub samples: u32, // shared by every attachment, 1 is not multisampled
+}
+
+impl Default for RenderTargetOptions {
+    fn default() -> Self {
+        Self {
+            colors: Vec::new(),
+            depth_stencil: None,
+            samples: 1,
+        }
+    }
+}
+
+impl RenderTargetOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color(mut self, internal_format: gl::types::GLenum) -> Self {
+        self.colors.push(internal_format);
+        self
+    }
+
+    // Renderbuffer, written by the depth and stencil tests but never sampled
+    pub fn depth_stencil(mut self, internal_format: gl::types::GLenum) -> Self {
+        self.depth_stencil = Some(internal_format);
+        self
+    }
+
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+}
+
+/**
+    Offscreen framebuffer owning its attachments. Color attachments are textures,
+    so a resolved target can be sampled by a later pass.
+    Resizing keeps every id, models holding a color texture stay valid.
+**/
+pub struct RenderTarget {
+    gl: gl::GlPtr,
+    id: gl::types::GLuint,
+    options: RenderTargetOptions,
+    colors: Vec<Texture>,
+    depth_stencil: Option<gl::types::GLuint>, // renderbuffer
+    size: Cell<(u32, u32)>,
+}
+
+impl RenderTarget {
+    pub fn new(
+        gl: &gl::GlPtr,
+        width: u32,
+        height: u32,
+        options: &RenderTargetOptions,
+    ) -> Result<Self, FramebufferError> {
+        let (mut max_samples, mut max_colors) = (0, 0);
+
+        unsafe {
+            gl.GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+            gl.GetIntegerv(gl::MAX_COLOR_ATTACHMENTS, &mut max_colors);
+        }
+
+        if options.samples > max_samples as u32 {
+            return Err(FramebufferError::TooManySamples {
+                requested: options.samples,
+                max: max_samples as u32,
+            });
+        }
+        if options.colors.len() > max_colors as usize {
+            return Err(FramebufferError::TooManyAttachments {
+                requested: options.colors.len(),
+                max: max_colors as usize,
+            });
+        }
+
+        let colors: Vec<Texture> = options
+            .colors
+            .iter()
+            .map(|&format| Texture::new_attachment(gl, format, width, height, options.samples))
+            .collect();
+
+        let mut id = 0;
+        let mut depth_stencil = None;
+
+        unsafe {
+            gl.GenFramebuffers(1, &mut id);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, id);
+
+            for (i, color) in colors.iter().enumerate() {
+                gl.FramebufferTexture2D(
+                    gl::FRAMEBUFFER,
+                    gl::COLOR_ATTACHMENT0 + i as u32,
+                    color.target,
+                    color.id,
+                    0,
+                );
+            }
+
+            // fragment outputs go to the attachments in order
+            let buffers = draw_buffers(colors.len());
+            if buffers.is_empty() {
+                gl.DrawBuffer(gl::NONE);
+                gl.ReadBuffer(gl::NONE);
+            } else {
+                gl.DrawBuffers(buffers.len() as gl::types::GLsizei, buffers.as_ptr());
+            }
+
+            if let Some(format) = options.depth_stencil {
+                let mut rbo = 0;
+                gl.GenRenderbuffers(1, &mut rbo);
+                renderbuffer_storage(gl, rbo, format, width, height, options.samples);
+
+                let attachment = match format {
+                    gl::DEPTH24_STENCIL8 | gl::DEPTH32F_STENCIL8 => gl::DEPTH_STENCIL_ATTACHMENT,
+                    _ => gl::DEPTH_ATTACHMENT,
+                };
+                gl.FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, rbo);
+                depth_stencil = Some(rbo);
+            }
+        }
+
+        // from here Drop cleans up
+        let target = Self {
+            gl: gl.clone(),
+            id,
+            options: options.clone(),
+            colors,
+            depth_stencil,
+            size: Cell::new((width, height)),
+        };
+
+        let status = unsafe { gl.CheckFramebufferStatus(gl::FRAMEBUFFER) };
+        RenderTarget::unbind(gl);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(FramebufferError::Incomplete(status));
+        }
+
+        Ok(target)
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.id
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size.get()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.options.samples
+    }
+
+    pub fn color_format(&self, index: usize) -> gl::types::GLenum {
+        self.options.colors[index]
+    }
+
+    // Multisampled color textures cannot be sampled by sampler2D, resolve them first
+    pub fn color(&self, index: usize) -> &Texture {
+        &self.colors[index]
+    }
+
+    // Reallocates every attachment when the size changed, cheap to call every frame
+    pub fn resize(&self, width: u32, height: u32) {
+        if self.size.get() == (width, height) || width == 0 || height == 0 {
+            return;
+        }
+        self.size.set((width, height));
+
+        for (color, &format) in self.colors.iter().zip(&self.options.colors) {
+            color.resize_attachment(format, width, height, self.options.samples);
+        }
+
+        if let (Some(rbo), Some(format)) = (self.depth_stencil, self.options.depth_stencil) {
+            renderbuffer_storage(&self.gl, rbo, format, width, height, self.options.samples);
+        }
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        }
+    }
+
+    // Back to the window
+    pub fn unbind(gl: &gl::GlPtr) {
+        unsafe {
+            gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /**
+        RESOLVE AND BLIT
+    **/
+
+    // Every color attachment into the one with the same index, e.g. multisampled to sampleable
+    pub fn resolve_into(&self, destination: &RenderTarget) {
+        for i in 0..self.colors.len().min(destination.colors.len()) {
+            self.resolve_attachment(i, destination, i);
+        }
+    }
+
+    pub fn resolve_attachment(&self, index: usize, destination: &RenderTarget, into: usize) {
+        let read = gl::COLOR_ATTACHMENT0 + index as u32;
+        let draw = gl::COLOR_ATTACHMENT0 + into as u32;
+
+        unsafe {
+            self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            self.gl
+                .BindFramebuffer(gl::DRAW_FRAMEBUFFER, destination.id);
+
+            self.gl.ReadBuffer(read);
+            self.gl.DrawBuffers(1, &draw);
+            blit(
+                &self.gl,
+                self.size(),
+                destination.size(),
+                gl::COLOR_BUFFER_BIT,
+            );
+
+            // restore what new() set up
+            self.gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+            let buffers = draw_buffers(destination.colors.len());
+            self.gl
+                .DrawBuffers(buffers.len() as gl::types::GLsizei, buffers.as_ptr());
+        }
+
+        RenderTarget::unbind(&self.gl);
+    }
+
+    // Buffers in mask (COLOR_BUFFER_BIT, DEPTH_BUFFER_BIT...) into another target, stretched to its size
+    pub fn blit_to(&self, destination: &RenderTarget, mask: gl::types::GLbitfield) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            self.gl
+                .BindFramebuffer(gl::DRAW_FRAMEBUFFER, destination.id);
+        }
+        blit(&self.gl, self.size(), destination.size(), mask);
+        RenderTarget::unbind(&self.gl);
+    }
+
+    // First color attachment into the window, width and height of the drawable
+    pub fn blit_to_screen(&self, width: u32, height: u32) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            self.gl.BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+        }
+        blit(&self.gl, self.size(), (width, height), gl::COLOR_BUFFER_BIT);
+        RenderTarget::unbind(&self.gl);
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(rbo) = self.depth_stencil {
+                self.gl.DeleteRenderbuffers(1, &rbo);
+            }
+            self.gl.DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
+
+fn draw_buffers(count: usize) -> Vec<gl::types::GLenum> {
+    (0..count as u32)
+        .map(|i| gl::COLOR_ATTACHMENT0 + i)
+        .collect()
+}
+
+fn renderbuffer_storage(
+    gl: &gl::GlPtr,
+    rbo: gl::types::GLuint,
+    format: gl::types::GLenum,
+    width: u32,
+    height: u32,
+    samples: u32,
+) {
+    unsafe {
+        gl.BindRenderbuffer(gl::RENDERBUFFER, rbo);
+        gl.RenderbufferStorageMultisample(
+            gl::RENDERBUFFER,
+            if samples > 1 { samples } else { 0 } as gl::types::GLsizei,
+            format,
+            width as gl::types::GLint,
+            height as gl::types::GLint,
+        );
+        gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+    }
+}
+
+// Between the bound READ and DRAW framebuffers, linear only when stretching color
+fn blit(
+    gl: &gl::GlPtr,
+    (src_width, src_height): (u32, u32),
+    (dst_width, dst_height): (u32, u32),
+    mask: gl::types::GLbitfield,
+) {
+    // depth and stencil can only be blitted with NEAREST
+    let filter =
+        if mask == gl::COLOR_BUFFER_BIT && (src_width, src_height) != (dst_width, dst_height) {
+            gl::LINEAR
+        } else {
+            gl::NEAREST
+        };
+
+    unsafe {
+        gl.BlitFramebuffer(
+            0,
+            0,
+            src_width as gl::types::GLint,
+            src_height as gl::types::GLint,
+            0,
+            0,
+            dst_width as gl::types::GLint,
+            dst_height as gl::types::GLint,
+            mask,
+            filter,
+        );
+    }
+}
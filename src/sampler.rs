@@ -0,0 +1,131 @@
+use crate::texture::{max_anisotropy, Filter};
+use gl;
+
+/**
+    GL sampler object, overrides the sampling state of whatever texture is bound
+    to the same unit. One sampler can be shared by any number of bindings:
+    Sampler::new(&gl).wrap(gl::REPEAT).mipmaps(Filter::Linear).anisotropy(16.)
+**/
+pub struct Sampler {
+    gl: gl::GlPtr,
+    id: gl::types::GLuint,
+    filter: Filter,
+    mipmaps: Option<Filter>,
+}
+
+impl Sampler {
+    // Linear, clamped, no mipmaps
+    pub fn new(gl: &gl::GlPtr) -> Self {
+        let mut id = 0;
+
+        unsafe {
+            gl.GenSamplers(1, &mut id);
+        }
+
+        Self {
+            gl: gl.clone(),
+            id,
+            filter: Filter::Linear,
+            mipmaps: None,
+        }
+        .wrap(gl::CLAMP_TO_EDGE)
+        .filter(Filter::Linear)
+    }
+
+    pub fn id(&self) -> gl::types::GLuint {
+        self.id
+    }
+
+    fn parameter(&self, name: gl::types::GLenum, value: gl::types::GLenum) {
+        unsafe {
+            self.gl
+                .SamplerParameteri(self.id, name, value as gl::types::GLint);
+        }
+    }
+
+    fn parameter_f(&self, name: gl::types::GLenum, value: f32) {
+        unsafe {
+            self.gl.SamplerParameterf(self.id, name, value);
+        }
+    }
+
+    pub fn wrap(self, wrap: gl::types::GLenum) -> Self {
+        self.parameter(gl::TEXTURE_WRAP_S, wrap);
+        self.parameter(gl::TEXTURE_WRAP_T, wrap);
+        self.parameter(gl::TEXTURE_WRAP_R, wrap);
+        self
+    }
+
+    // Magnification and filtering within a mip level
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self.update_filters();
+        self
+    }
+
+    // Filtering between mip levels, texture needs a mip chain
+    pub fn mipmaps(mut self, between_levels: Filter) -> Self {
+        self.mipmaps = Some(between_levels);
+        self.update_filters();
+        self
+    }
+
+    fn update_filters(&self) {
+        let min = match (self.filter, self.mipmaps) {
+            (Filter::Nearest, None) => gl::NEAREST,
+            (Filter::Linear, None) => gl::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Nearest, Some(Filter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, Some(Filter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Linear, Some(Filter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let mag = match self.filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        };
+
+        self.parameter(gl::TEXTURE_MIN_FILTER, min);
+        self.parameter(gl::TEXTURE_MAG_FILTER, mag);
+    }
+
+    // Clamped to what the driver supports, 1 disables it
+    pub fn anisotropy(self, anisotropy: f32) -> Self {
+        self.parameter_f(
+            gl::TEXTURE_MAX_ANISOTROPY_EXT,
+            anisotropy.min(max_anisotropy()).max(1.),
+        );
+        self
+    }
+
+    // Depth comparison for shadow samplers, e.g. gl::LEQUAL
+    pub fn compare(self, function: gl::types::GLenum) -> Self {
+        self.parameter(gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE);
+        self.parameter(gl::TEXTURE_COMPARE_FUNC, function);
+        self
+    }
+
+    pub fn lod_bias(self, bias: f32) -> Self {
+        self.parameter_f(gl::TEXTURE_LOD_BIAS, bias);
+        self
+    }
+
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            self.gl.BindSampler(unit, self.id);
+        }
+    }
+
+    pub fn unbind(gl: &gl::GlPtr, unit: u32) {
+        unsafe {
+            gl.BindSampler(unit, 0);
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteSamplers(1, &self.id);
+        }
+    }
+}
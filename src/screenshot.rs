@@ -0,0 +1,546 @@
+extern crate png;
+
+use crate::render_target::{RenderTarget, RenderTargetOptions};
+use crate::texture::{Image, PixelData};
+use gl;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+/**
+    READBACK
+    Images come back as RGBA with the top row first, ready to be saved
+**/
+
+// Back buffer of the window, call before swapping
+pub fn read_screen(gl: &gl::GlPtr, width: u32, height: u32) -> Image {
+    unsafe {
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl.ReadBuffer(gl::BACK);
+    }
+
+    read_pixels(gl, width, height, false)
+}
+
+// One color attachment, float formats keep their range, multisampled targets are resolved first
+pub fn read_target(gl: &gl::GlPtr, target: &RenderTarget, index: usize) -> Result<Image, String> {
+    let (width, height) = target.size();
+    let format = target.color_format(index);
+    let hdr = is_float_format(format);
+
+    if target.samples() > 1 {
+        let resolved =
+            RenderTarget::new(gl, width, height, &RenderTargetOptions::new().color(format))?;
+        target.resolve_attachment(index, &resolved, 0);
+        return read_target(gl, &resolved, 0);
+    }
+
+    unsafe {
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, target.id());
+        gl.ReadBuffer(gl::COLOR_ATTACHMENT0 + index as u32);
+    }
+
+    let image = read_pixels(gl, width, height, hdr);
+
+    unsafe {
+        gl.ReadBuffer(gl::COLOR_ATTACHMENT0);
+    }
+    RenderTarget::unbind(gl);
+
+    Ok(image)
+}
+
+fn read_pixels(gl: &gl::GlPtr, width: u32, height: u32, hdr: bool) -> Image {
+    let count = (width * height * 4) as usize;
+    let size = (width as gl::types::GLsizei, height as gl::types::GLsizei);
+
+    let data = unsafe {
+        // rows are tightly packed
+        gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+
+        if hdr {
+            let mut pixels = vec![0f32; count];
+            let pointer = pixels.as_mut_ptr() as *mut gl::types::GLvoid;
+            gl.ReadPixels(0, 0, size.0, size.1, gl::RGBA, gl::FLOAT, pointer);
+            PixelData::F32(pixels)
+        } else {
+            let mut pixels = vec![0u8; count];
+            let pointer = pixels.as_mut_ptr() as *mut gl::types::GLvoid;
+            gl.ReadPixels(0, 0, size.0, size.1, gl::RGBA, gl::UNSIGNED_BYTE, pointer);
+            PixelData::U8(pixels)
+        }
+    };
+
+    unsafe {
+        gl.PixelStorei(gl::PACK_ALIGNMENT, 4);
+        gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    }
+
+    // GL starts at the bottom row
+    let mut image = Image {
+        width,
+        height,
+        channels: 4,
+        data,
+    };
+    image.flip_vertically();
+    image
+}
+
+fn is_float_format(format: gl::types::GLenum) -> bool {
+    matches!(
+        format,
+        gl::R16F
+            | gl::RG16F
+            | gl::RGB16F
+            | gl::RGBA16F
+            | gl::R32F
+            | gl::RG32F
+            | gl::RGB32F
+            | gl::RGBA32F
+            | gl::R11F_G11F_B10F
+    )
+}
+
+/**
+    SAVING
+**/
+
+// screenshots/screenshot_2020-06-01_12-30-05_042.png, .exr for float images
+pub fn save<P: AsRef<Path>>(image: &Image, directory: P) -> Result<PathBuf, String> {
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)
+        .map_err(|error| format!("cannot create {}: {}", directory.display(), error))?;
+
+    let extension = match image.data {
+        PixelData::F32(_) => "exr",
+        _ => "png",
+    };
+    let path = directory.join(format!("screenshot_{}.{}", timestamp(), extension));
+
+    match image.data {
+        PixelData::F32(_) => save_exr(image, &path)?,
+        _ => save_png(image, &path)?,
+    }
+
+    Ok(path)
+}
+
+// 8 or 16 bits per channel, float images are clamped to 8 bits
+pub fn save_png<P: AsRef<Path>>(image: &Image, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    let error =
+        |error: &dyn std::fmt::Display| format!("cannot write {}: {}", path.display(), error);
+
+    let file = std::fs::File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), image.width, image.height);
+
+    encoder.set_color(match image.channels {
+        1 => png::ColorType::Grayscale,
+        2 => png::ColorType::GrayscaleAlpha,
+        3 => png::ColorType::RGB,
+        _ => png::ColorType::RGBA,
+    });
+
+    let bytes = match &image.data {
+        PixelData::U8(data) => {
+            encoder.set_depth(png::BitDepth::Eight);
+            data.clone()
+        }
+        PixelData::U16(data) => {
+            // png is big endian
+            encoder.set_depth(png::BitDepth::Sixteen);
+            data.iter()
+                .flat_map(|value| value.to_be_bytes().to_vec())
+                .collect()
+        }
+        PixelData::F32(data) => {
+            encoder.set_depth(png::BitDepth::Eight);
+            data.iter()
+                .map(|value| (value.clamp(0., 1.) * 255. + 0.5) as u8)
+                .collect()
+        }
+    };
+
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer.write_image_data(&bytes).map_err(|e| error(&e))
+}
+
+// Uncompressed scanline OpenEXR with 32-bit float channels, enough for any viewer
+pub fn save_exr<P: AsRef<Path>>(image: &Image, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+
+    let data = match &image.data {
+        PixelData::F32(data) => data,
+        _ => {
+            return Err(format!(
+                "{}: only float images are saved as exr",
+                path.display()
+            ))
+        }
+    };
+
+    let file = std::fs::File::create(path)
+        .map_err(|error| format!("cannot write {}: {}", path.display(), error))?;
+    std::io::BufWriter::new(file)
+        .write_all(&exr_bytes(image.width, image.height, image.channels, data))
+        .map_err(|error| format!("cannot write {}: {}", path.display(), error))
+}
+
+fn exr_bytes(width: u32, height: u32, channels: u32, data: &[f32]) -> Vec<u8> {
+    // channels are stored in alphabetical order, value is the index in a pixel
+    let names: &[(&str, usize)] = match channels {
+        1 => &[("Y", 0)],
+        2 => &[("A", 1), ("Y", 0)],
+        3 => &[("B", 2), ("G", 1), ("R", 0)],
+        _ => &[("A", 3), ("B", 2), ("G", 1), ("R", 0)],
+    };
+
+    let mut bytes = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+
+    let attribute = |bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(kind.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(value);
+    };
+
+    let mut channel_list = Vec::new();
+    for (name, _) in names {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        channel_list.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    attribute(&mut bytes, "channels", "chlist", &channel_list);
+    attribute(&mut bytes, "compression", "compression", &[0]);
+    attribute(&mut bytes, "dataWindow", "box2i", &window);
+    attribute(&mut bytes, "displayWindow", "box2i", &window);
+    attribute(&mut bytes, "lineOrder", "lineOrder", &[0]); // top row first
+    attribute(&mut bytes, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut bytes,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    bytes.push(0);
+
+    // one scanline per block, offsets from the start of the file
+    let line_size = 8 + width as usize * names.len() * 4;
+    let first_line = bytes.len() + height as usize * 8;
+    for y in 0..height as usize {
+        bytes.extend_from_slice(&((first_line + y * line_size) as u64).to_le_bytes());
+    }
+
+    let channels = channels as usize;
+    for y in 0..height as usize {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&((line_size - 8) as i32).to_le_bytes());
+
+        let row = &data[y * width as usize * channels..(y + 1) * width as usize * channels];
+        for &(_, index) in names {
+            for pixel in row.chunks(channels) {
+                bytes.extend_from_slice(&pixel[index].to_le_bytes());
+            }
+        }
+    }
+
+    bytes
+}
+
+// Local clock is not available without a dependency, UTC date and time
+fn timestamp() -> String {
+    timestamp_at(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default(),
+    )
+}
+
+// Time since the epoch as 2020-06-01_12-30-05_042
+fn timestamp_at(now: std::time::Duration) -> String {
+    let seconds = now.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}_{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+// Days since 1970-01-01 to a gregorian date (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/**
+    SELF TEST
+    Clears two halves of offscreen targets, reads them back and saves them
+**/
+
+pub fn self_test(gl: &gl::GlPtr) -> Result<(), String> {
+    let (width, height) = (4, 4);
+
+    // bottom half red, top half green
+    let fill = |target: &RenderTarget, bottom: [f32; 4], top: [f32; 4]| unsafe {
+        target.bind();
+        gl.Viewport(0, 0, width as i32, height as i32);
+        gl.Enable(gl::SCISSOR_TEST);
+        for &(y, color) in &[(0, bottom), (height as i32 / 2, top)] {
+            gl.Scissor(0, y, width as i32, height as i32 / 2);
+            gl.ClearColor(color[0], color[1], color[2], color[3]);
+            gl.Clear(gl::COLOR_BUFFER_BIT);
+        }
+        gl.Disable(gl::SCISSOR_TEST);
+        RenderTarget::unbind(gl);
+    };
+
+    let first_and_last = |image: &Image| -> Result<(Vec<f32>, Vec<f32>), String> {
+        let last = ((width * height - 1) * 4) as usize;
+        match &image.data {
+            PixelData::U8(data) => Ok((
+                data[..4].iter().map(|&v| v as f32 / 255.).collect(),
+                data[last..last + 4]
+                    .iter()
+                    .map(|&v| v as f32 / 255.)
+                    .collect(),
+            )),
+            PixelData::F32(data) => Ok((data[..4].to_vec(), data[last..last + 4].to_vec())),
+            PixelData::U16(_) => Err("unexpected 16-bit readback".to_string()),
+        }
+    };
+
+    let expect = |what: &str, image: &Image, top: [f32; 4], bottom: [f32; 4]| {
+        let (first, last) = first_and_last(image)?;
+        if first != top || last != bottom {
+            return Err(format!(
+                "{}: expected top {:?} and bottom {:?}, got {:?} and {:?}",
+                what, top, bottom, first, last
+            ));
+        }
+        Ok(())
+    };
+
+    let red = [1., 0., 0., 1.];
+    let green = [0., 1., 0., 1.];
+
+    // multisampled, goes through a resolve
+    let ldr = RenderTarget::new(
+        gl,
+        width,
+        height,
+        &RenderTargetOptions::new().color(gl::RGBA8).samples(4),
+    )?;
+    fill(&ldr, red, green);
+    let image = read_target(gl, &ldr, 0)?;
+    expect("rgba8", &image, green, red)?;
+
+    let png_path = std::env::temp_dir().join("screenshot_self_test.png");
+    save_png(&image, &png_path)?;
+    let loaded = Image::load(&png_path)?;
+    let _ = std::fs::remove_file(&png_path);
+    expect("png round trip", &loaded, green, red)?;
+
+    // values above 1 survive in float targets
+    let bright = [4., 0.5, 0., 1.];
+    let hdr = RenderTarget::new(
+        gl,
+        width,
+        height,
+        &RenderTargetOptions::new().color(gl::RGBA16F),
+    )?;
+    fill(&hdr, bright, green);
+    let image = read_target(gl, &hdr, 0)?;
+    expect("rgba16f", &image, green, bright)?;
+
+    let exr_path = std::env::temp_dir().join("screenshot_self_test.exr");
+    save_exr(&image, &exr_path)?;
+    let _ = std::fs::remove_file(&exr_path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(18414), (2020, 6, 1));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-25508), (1900, 3, 1));
+    }
+
+    #[test]
+    fn timestamps_sort_and_fit_in_file_names() {
+        let seconds = 18414 * 86400 + 12 * 3600 + 30 * 60 + 5;
+        assert_eq!(
+            timestamp_at(Duration::new(seconds, 42_000_000)),
+            "2020-06-01_12-30-05_042"
+        );
+        assert_eq!(timestamp_at(Duration::new(0, 0)), "1970-01-01_00-00-00_000");
+        assert_eq!(
+            timestamp_at(Duration::new(seconds + 86399 - 45005, 999_999_999)),
+            "2020-06-01_23-59-59_999"
+        );
+    }
+
+    // Reads a null terminated string at `at`, moves past it
+    fn string(bytes: &[u8], at: &mut usize) -> String {
+        let end = *at + bytes[*at..].iter().position(|&b| b == 0).unwrap();
+        let text = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        text
+    }
+
+    fn i32_at(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    fn f32_at(bytes: &[u8], at: usize) -> f32 {
+        f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_a_scanline_exr() {
+        let (width, height) = (3, 2);
+        // rgba, value encodes channel, x and y
+        let data: Vec<f32> = (0..height * width * 4)
+            .map(|i| (i % 4) as f32 + (i / 4 % width) as f32 * 10. + (i / 4 / width) as f32 * 100.)
+            .collect();
+        let bytes = exr_bytes(width, height, 4, &data);
+
+        assert_eq!(bytes[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // header, attributes until an empty name
+        let mut at = 8;
+        let mut attributes = vec![];
+        loop {
+            let name = string(&bytes, &mut at);
+            if name.is_empty() {
+                break;
+            }
+            let kind = string(&bytes, &mut at);
+            let size = i32_at(&bytes, at) as usize;
+            attributes.push((name, kind, bytes[at + 4..at + 4 + size].to_vec()));
+            at += 4 + size;
+        }
+
+        let names: Vec<&str> = attributes
+            .iter()
+            .map(|(name, _, _)| name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "channels",
+                "compression",
+                "dataWindow",
+                "displayWindow",
+                "lineOrder",
+                "pixelAspectRatio",
+                "screenWindowCenter",
+                "screenWindowWidth"
+            ]
+        );
+
+        // channels sorted by name, all FLOAT
+        let channel_list = &attributes[0].2;
+        let mut channel_at = 0;
+        let mut channels = vec![];
+        while channel_list[channel_at] != 0 {
+            channels.push(string(channel_list, &mut channel_at));
+            assert_eq!(i32_at(channel_list, channel_at), 2);
+            channel_at += 16;
+        }
+        assert_eq!(channels, ["A", "B", "G", "R"]);
+        assert_eq!(attributes[1].2, [0]); // no compression
+        assert_eq!(
+            (0..4)
+                .map(|i| i32_at(&attributes[2].2, i * 4))
+                .collect::<Vec<_>>(),
+            [0, 0, width as i32 - 1, height as i32 - 1]
+        );
+
+        // offset table points at consecutive scanlines right after it
+        let line_size = 8 + width as usize * 4 * 4;
+        let offsets: Vec<usize> = (0..height as usize)
+            .map(|y| {
+                u64::from_le_bytes(bytes[at + y * 8..at + y * 8 + 8].try_into().unwrap()) as usize
+            })
+            .collect();
+        assert_eq!(offsets[0], at + height as usize * 8);
+        assert_eq!(offsets[1], offsets[0] + line_size);
+        assert_eq!(bytes.len(), offsets[1] + line_size);
+
+        // each scanline: y, size, then every pixel of A, of B, of G, of R
+        for (y, &offset) in offsets.iter().enumerate() {
+            assert_eq!(i32_at(&bytes, offset), y as i32);
+            assert_eq!(i32_at(&bytes, offset + 4) as usize, line_size - 8);
+
+            for (plane, &channel) in [3, 2, 1, 0].iter().enumerate() {
+                for x in 0..width as usize {
+                    let value = f32_at(&bytes, offset + 8 + (plane * width as usize + x) * 4);
+                    assert_eq!(value, channel as f32 + x as f32 * 10. + y as f32 * 100.);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_gray_and_rgb_channels() {
+        let rgb = exr_bytes(1, 1, 3, &[0.25, 0.5, 0.75]);
+        let pixels = rgb.len() - 12;
+        assert_eq!(
+            (0..3)
+                .map(|i| f32_at(&rgb, pixels + i * 4))
+                .collect::<Vec<_>>(),
+            [0.75, 0.5, 0.25]
+        );
+
+        let gray = exr_bytes(1, 1, 1, &[2.]);
+        assert_eq!(f32_at(&gray, gray.len() - 4), 2.);
+        assert!(gray.windows(3).any(|w| w == b"Y\0\x02"));
+    }
+}
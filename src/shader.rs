@@ -1,6 +1,15 @@
+use crate::preprocessor::{self, Preprocessed};
+use crate::program_cache;
+use crate::reflection::ProgramReflection;
+use crate::shader_error::{stage_name, ShaderError};
+use crate::uniform::{type_name, Uniform, UniformInfo};
 use gl;
 use std;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
 
 type Stringable = Into<Vec<u8>>;
 
@@ -10,24 +19,42 @@ pub struct Shader {
 }
 
 impl Shader {
+    // Log lines are reported against the stage name, e.g. "fragment:12: ..."
     pub fn from_source(
         gl: &gl::GlPtr,
         source: &CStr,
         kind: gl::types::GLenum,
-    ) -> Result<Shader, String> {
-        let id = shader_from_source(gl, source, kind)?;
+    ) -> Result<Shader, ShaderError> {
+        let id = shader_from_source(gl, source, kind).map_err(|error| {
+            error.locate(
+                &[stage_name(kind).to_string()],
+                &[source.to_string_lossy().into_owned()],
+            )
+        })?;
+        Ok(Shader { gl: gl.clone(), id })
+    }
+
+    // Log lines are reported against the included files
+    pub fn from_preprocessed(
+        gl: &gl::GlPtr,
+        preprocessed: &Preprocessed,
+        kind: gl::types::GLenum,
+    ) -> Result<Shader, ShaderError> {
+        let source = c_source(&preprocessed.files[0], &preprocessed.source)?;
+        let id = shader_from_source(gl, &source, kind)
+            .map_err(|error| error.locate(&preprocessed.files, &preprocessed.sources))?;
         Ok(Shader { gl: gl.clone(), id })
     }
 
-    pub fn from_vert_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, String> {
+    pub fn from_vert_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, ShaderError> {
         Shader::from_source(gl, source, gl::VERTEX_SHADER)
     }
 
-    pub fn from_frag_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, String> {
+    pub fn from_frag_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, ShaderError> {
         Shader::from_source(gl, source, gl::FRAGMENT_SHADER)
     }
 
-    pub fn from_comp_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, String> {
+    pub fn from_comp_source(gl: &gl::GlPtr, source: &CStr) -> Result<Shader, ShaderError> {
         Shader::from_source(gl, source, gl::COMPUTE_SHADER)
     }
 
@@ -47,17 +74,33 @@ impl Drop for Shader {
 pub struct Program {
     gl: gl::GlPtr,
     id: gl::types::GLuint,
+    reflection: ProgramReflection,
+
+    // filled with active uniforms at link time, other names are added on first use
+    uniforms: RefCell<HashMap<String, UniformInfo>>,
+    warned: RefCell<HashSet<String>>,
+    pub warn_uniforms: bool, // print unknown names and type mismatches once
+
+    watch: Option<Watch>, // debug builds only
 }
 
 impl Program {
-    pub fn from_shaders(gl: &gl::GlPtr, shaders: &[Shader]) -> Result<Program, String> {
+    pub fn from_shaders(gl: &gl::GlPtr, shaders: &[Shader]) -> Result<Program, ShaderError> {
         let program_id = unsafe { gl.CreateProgram() };
 
         for shader in shaders {
             unsafe { gl.AttachShader(program_id, shader.id()) }
         }
 
-        unsafe { gl.LinkProgram(program_id) }
+        unsafe {
+            // for program_cache
+            gl.ProgramParameteri(
+                program_id,
+                gl::PROGRAM_BINARY_RETRIEVABLE_HINT,
+                gl::TRUE as gl::types::GLint,
+            );
+            gl.LinkProgram(program_id);
+        }
 
         let mut success: gl::types::GLint = 1;
         unsafe {
@@ -67,51 +110,126 @@ impl Program {
         if success == 0 {
             let mut len: gl::types::GLint = 0;
             unsafe {
-                gl.GetShaderiv(program_id, gl::INFO_LOG_LENGTH, &mut len);
+                gl.GetProgramiv(program_id, gl::INFO_LOG_LENGTH, &mut len);
             }
 
             let error = create_whitespace_cstring_with_len(len as usize);
             unsafe {
-                gl.GetShaderInfoLog(
+                gl.GetProgramInfoLog(
                     program_id,
                     len,
                     std::ptr::null_mut(),
                     error.as_ptr() as *mut gl::types::GLchar,
-                )
+                );
+                gl.DeleteProgram(program_id);
             }
 
-            return Err(error.to_string_lossy().into_owned());
+            return Err(ShaderError::Link {
+                log: error.to_string_lossy().into_owned(),
+            });
         }
 
         for shader in shaders {
             unsafe { gl.DetachShader(program_id, shader.id()) }
         }
 
-        Ok(Program {
+        Ok(Self::from_linked(gl, program_id))
+    }
+
+    fn from_linked(gl: &gl::GlPtr, program_id: gl::types::GLuint) -> Program {
+        let reflection = ProgramReflection::new(gl, program_id);
+
+        Program {
             gl: gl.clone(),
             id: program_id,
-        })
+            uniforms: RefCell::new(uniform_cache(&reflection)),
+            reflection,
+            warned: RefCell::new(HashSet::new()),
+            warn_uniforms: cfg!(debug_assertions),
+            watch: None,
+        }
+    }
+
+    // Read from disk in debug builds so the program can be reloaded, embedded otherwise
+    pub fn from_sources(
+        gl: &gl::GlPtr,
+        sources: Vec<ShaderSource>,
+    ) -> Result<Program, ShaderError> {
+        Self::from_variant(gl, &sources, &[])
+    }
+
+    // Sources preprocessed with extra `#define`s, "NAME" or "NAME VALUE"
+    pub fn from_variant(
+        gl: &gl::GlPtr,
+        sources: &[ShaderSource],
+        defines: &[String],
+    ) -> Result<Program, ShaderError> {
+        let (mut program, files) = compile_sources(gl, sources, defines)?;
+
+        if cfg!(debug_assertions) {
+            program.watch = Some(Watch::new(sources.to_vec(), defines.to_vec(), files));
+        }
+
+        Ok(program)
     }
 
     pub fn from_files(
         gl: &gl::GlPtr,
         vert_path: &'static str,
         frag_path: &'static str,
-    ) -> Result<Program, String> {
-        use std::ffi::CString;
-
-        let vert_shader = Shader::from_vert_source(&gl, &CString::new(vert_path).unwrap())?;
-        let frag_shader = Shader::from_frag_source(&gl, &CString::new(frag_path).unwrap())?;
+    ) -> Result<Program, ShaderError> {
+        let vert_shader = Shader::from_vert_source(&gl, &c_source("vertex", vert_path)?)?;
+        let frag_shader = Shader::from_frag_source(&gl, &c_source("fragment", frag_path)?)?;
 
         Self::from_shaders(&gl, &[vert_shader, frag_shader])
     }
 
-    pub fn from_compute_shader_file(gl: &gl::GlPtr, path: &'static str) -> Result<Program, String> {
-        use std::ffi::CString;
-        let comp_shader = Shader::from_comp_source(&gl, &CString::new(path).unwrap())?;
+    pub fn from_compute_shader_file(
+        gl: &gl::GlPtr,
+        path: &'static str,
+    ) -> Result<Program, ShaderError> {
+        let comp_shader = Shader::from_comp_source(&gl, &c_source("compute", path)?)?;
         Self::from_shaders(&gl, &[comp_shader])
     }
 
+    /**
+        HOT RELOAD
+    **/
+
+    // Recompile if a source file changed, old program is kept when it fails
+    pub fn reload_if_changed(&mut self) -> bool {
+        let watch = match &mut self.watch {
+            Some(watch) => watch,
+            None => return false,
+        };
+
+        if !watch.changed() {
+            return false;
+        }
+
+        match compile_sources(&self.gl, &watch.sources, &watch.defines) {
+            Ok((mut program, files)) => {
+                watch.set_files(files);
+
+                // old id is deleted when `program` drops
+                std::mem::swap(&mut self.id, &mut program.id);
+                std::mem::swap(&mut self.reflection, &mut program.reflection);
+                std::mem::swap(&mut self.uniforms, &mut program.uniforms);
+                self.warned.borrow_mut().clear();
+
+                println!("Program {}: reloaded", self.id);
+                true
+            }
+            Err(error) => {
+                println!(
+                    "Program {}: reload failed, keeping old one\n{}",
+                    self.id, error
+                );
+                false
+            }
+        }
+    }
+
     pub fn bind(&self) {
         unsafe { self.gl.UseProgram(self.id) }
     }
@@ -120,82 +238,332 @@ impl Program {
         self.id
     }
 
-    pub fn setMat4(&self, val: &glm::Mat4, name: &'static str) {
+    pub fn reflection(&self) -> &ProgramReflection {
+        &self.reflection
+    }
+
+    /**
+        UNIFORMS
+    **/
+
+    // Cached location, array elements and unknown names are looked up once
+    pub fn uniform_info(&self, name: &str) -> UniformInfo {
+        if let Some(info) = self.uniforms.borrow().get(name) {
+            return info.clone();
+        }
+
         let cstr_name = CString::new(name).unwrap();
+        let location = unsafe { self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()) };
 
-        unsafe {
-            self.gl.UniformMatrix4fv(
-                self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()),
-                1,
-                gl::FALSE,
-                val.as_ptr(),
-            );
+        // element of an array takes type of the array
+        let kind = name
+            .rfind('[')
+            .and_then(|bracket| self.uniforms.borrow().get(&name[..bracket]).cloned())
+            .map(|array| array.kind)
+            .unwrap_or(0);
+
+        if location < 0 {
+            self.warn(name, || format!("unknown uniform {}", name));
         }
+
+        let info = UniformInfo {
+            location,
+            kind,
+            size: 1,
+        };
+
+        self.uniforms
+            .borrow_mut()
+            .insert(name.to_string(), info.clone());
+        info
     }
 
-    pub fn setFloat(&self, val: f32, name: &str) {
-        let cstr_name = CString::new(name).unwrap();
+    // Program has to be bound
+    pub fn set_uniform<T: Uniform>(&self, name: &str, value: &T) {
+        self.set_uniform_array(name, std::slice::from_ref(value));
+    }
 
-        unsafe {
-            self.gl
-                .Uniform1f(self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()), val);
+    pub fn set_uniform_array<T: Uniform>(&self, name: &str, values: &[T]) {
+        let info = self.uniform_info(name);
+
+        if info.location < 0 {
+            return;
+        }
+
+        if info.kind != 0 && !T::accepts(info.kind) {
+            self.warn(name, || {
+                format!(
+                    "uniform {} is {}, got {}",
+                    name,
+                    type_name(info.kind),
+                    std::any::type_name::<T>()
+                )
+            });
         }
+
+        if values.len() as i32 > info.size {
+            self.warn(name, || {
+                format!(
+                    "uniform {} has {} elements, got {}",
+                    name,
+                    info.size,
+                    values.len()
+                )
+            });
+        }
+
+        T::upload(&self.gl, info.location, values);
+    }
+
+    fn warn<F: FnOnce() -> String>(&self, name: &str, message: F) {
+        if self.warn_uniforms && self.warned.borrow_mut().insert(name.to_string()) {
+            println!("Program {}: {}", self.id, message());
+        }
+    }
+
+    pub fn setMat4(&self, val: &glm::Mat4, name: &str) {
+        self.set_uniform(name, val);
+    }
+
+    pub fn setFloat(&self, val: f32, name: &str) {
+        self.set_uniform(name, &val);
     }
 
     pub fn setInt(&self, val: i32, name: &str) {
-        let cstr_name = CString::new(name).unwrap();
+        self.set_uniform(name, &val);
+    }
 
+    pub fn setVec2Int(&self, val: &glm::I32Vec2, name: &str) {
+        self.set_uniform(name, val);
+    }
+
+    pub fn setVec3Float(&self, val: &glm::Vec3, name: &str) {
+        self.set_uniform(name, val);
+    }
+
+    pub fn setVec4Float(&self, val: &glm::Vec4, name: &str) {
+        self.set_uniform(name, val);
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
         unsafe {
-            self.gl
-                .Uniform1i(self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()), val);
+            self.gl.DeleteProgram(self.id);
         }
     }
+}
 
-    pub fn setVec2Int(&self, val: &glm::I32Vec2, name: &str) {
-        let cstr_name = CString::new(name).unwrap();
+// One stage, `path` is relative to the working directory
+#[derive(Copy, Clone)]
+pub struct ShaderSource {
+    pub kind: gl::types::GLenum,
+    pub path: &'static str,
+    pub embedded: &'static str,
+}
 
-        unsafe {
-            self.gl.Uniform2iv(
-                self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()),
-                1,
-                val.as_ptr(),
-            );
+// ShaderSource with the file embedded, path relative to src/ like include_str! in main.rs
+macro_rules! shader_source {
+    ($kind:expr, $path:literal) => {
+        crate::shader::ShaderSource {
+            kind: $kind,
+            path: concat!("src/", $path),
+            embedded: include_str!($path),
         }
+    };
+}
+
+impl ShaderSource {
+    // Missing file falls back to the embedded copy
+    fn read(&self) -> String {
+        preprocessor::load(self.path).unwrap_or_else(|| self.embedded.to_string())
     }
+}
 
-    pub fn setVec3Float(&self, val: &glm::Vec3, name: &str) {
-        let cstr_name = CString::new(name).unwrap();
+// Also returns every file that went in, includes too. Linked binary comes
+// from program_cache when the preprocessed sources did not change
+fn compile_sources(
+    gl: &gl::GlPtr,
+    sources: &[ShaderSource],
+    defines: &[String],
+) -> Result<(Program, Vec<String>), ShaderError> {
+    let mut files = vec![];
+    let mut stages = vec![];
+
+    for source in sources {
+        let preprocessed =
+            preprocessor::preprocess(source.path, &source.read(), defines).map_err(|message| {
+                ShaderError::InvalidSource {
+                    path: source.path.to_string(),
+                    message,
+                }
+            })?;
+        files.extend(preprocessed.files.iter().cloned());
+        stages.push((source.kind, preprocessed));
+    }
 
-        unsafe {
-            self.gl.Uniform3fv(
-                self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()),
-                1,
-                val.as_ptr(),
-            );
+    files.sort();
+    files.dedup();
+
+    let key = program_cache::key(
+        gl,
+        &stages
+            .iter()
+            .map(|(kind, preprocessed)| (*kind, preprocessed.source.as_str()))
+            .collect::<Vec<_>>(),
+    );
+
+    if let Some(program_id) = program_cache::load(gl, key) {
+        return Ok((Program::from_linked(gl, program_id), files));
+    }
+
+    let mut shaders = vec![];
+
+    for (kind, preprocessed) in &stages {
+        shaders.push(Shader::from_preprocessed(gl, preprocessed, *kind)?);
+    }
+
+    let program = Program::from_shaders(gl, &shaders)?;
+
+    if let Err(error) = program_cache::store(gl, key, program.id) {
+        println!("Program {}: not cached, {}", program.id, error);
+    }
+
+    Ok((program, files))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Polls modification times, no more often than CHECK_INTERVAL
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Watch {
+    sources: Vec<ShaderSource>,
+    defines: Vec<String>,
+    files: Vec<String>,
+    modified: Vec<Option<SystemTime>>,
+    last_check: Instant,
+}
+
+impl Watch {
+    fn new(sources: Vec<ShaderSource>, defines: Vec<String>, files: Vec<String>) -> Self {
+        let modified = files.iter().map(|file| modified(file)).collect();
+
+        Self {
+            sources,
+            defines,
+            files,
+            modified,
+            last_check: Instant::now(),
         }
     }
 
-    pub fn setVec4Float(&self, val: &glm::Vec4, name: &str) {
-        let cstr_name = CString::new(name).unwrap();
+    // Includes may have changed after a reload
+    fn set_files(&mut self, files: Vec<String>) {
+        self.modified = files.iter().map(|file| modified(file)).collect();
+        self.files = files;
+    }
 
-        unsafe {
-            self.gl.Uniform4fv(
-                self.gl.GetUniformLocation(self.id, cstr_name.as_ptr()),
-                1,
-                val.as_ptr(),
-            );
+    fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+
+        self.last_check = Instant::now();
+        let modified: Vec<Option<SystemTime>> =
+            self.files.iter().map(|file| modified(file)).collect();
+
+        if modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
         }
     }
 }
 
-impl Drop for Program {
-    fn drop(&mut self) {
-        unsafe {
-            self.gl.DeleteProgram(self.id);
+/**
+    VARIANTS
+**/
+
+// One set of sources compiled into permutations of defines, e.g. with and without parallax
+pub struct ProgramVariants {
+    gl: gl::GlPtr,
+    sources: Vec<ShaderSource>,
+    programs: HashMap<Vec<String>, Program>,
+}
+
+impl ProgramVariants {
+    pub fn new(gl: &gl::GlPtr, sources: Vec<ShaderSource>) -> Self {
+        Self {
+            gl: gl.clone(),
+            sources,
+            programs: HashMap::new(),
+        }
+    }
+
+    // order of defines does not matter
+    fn key(defines: &[&str]) -> Vec<String> {
+        let mut key: Vec<String> = defines.iter().map(|define| define.to_string()).collect();
+        key.sort();
+        key
+    }
+
+    // Compiles the variant once, later calls are free
+    pub fn prepare(&mut self, defines: &[&str]) -> Result<(), ShaderError> {
+        let key = Self::key(defines);
+
+        if !self.programs.contains_key(&key) {
+            let program = Program::from_variant(&self.gl, &self.sources, &key)?;
+            self.programs.insert(key, program);
+        }
+
+        Ok(())
+    }
+
+    // Only variants that were prepared
+    pub fn get(&self, defines: &[&str]) -> Option<&Program> {
+        self.programs.get(&Self::key(defines))
+    }
+
+    pub fn reload_if_changed(&mut self) {
+        for program in self.programs.values_mut() {
+            program.reload_if_changed();
         }
     }
 }
 
+// Locations of uniforms outside of blocks, by name
+fn uniform_cache(reflection: &ProgramReflection) -> HashMap<String, UniformInfo> {
+    let mut uniforms = HashMap::new();
+
+    for uniform in reflection.uniforms.iter().filter(|u| u.location >= 0) {
+        let info = UniformInfo {
+            location: uniform.location,
+            kind: uniform.kind,
+            size: uniform.size,
+        };
+
+        // arrays are reported as name[0], make plain name work too
+        if let Some(array_name) = uniform.name.strip_suffix("[0]") {
+            uniforms.insert(array_name.to_string(), info.clone());
+        }
+
+        uniforms.insert(uniform.name.clone(), info);
+    }
+
+    uniforms
+}
+
+fn c_source(path: &str, source: &str) -> Result<CString, ShaderError> {
+    CString::new(source).map_err(|error| ShaderError::InvalidSource {
+        path: path.to_string(),
+        message: error.to_string(),
+    })
+}
+
 fn create_whitespace_cstring_with_len(len: usize) -> CString {
     let mut buffer: Vec<u8> = Vec::with_capacity(len as usize + 1);
     buffer.extend([b' '].iter().cycle().take(len as usize));
@@ -206,7 +574,7 @@ fn shader_from_source(
     gl: &gl::GlPtr,
     source: &CStr,
     kind: gl::types::GLuint,
-) -> Result<gl::types::GLuint, String> {
+) -> Result<gl::types::GLuint, ShaderError> {
     let id = unsafe { gl.CreateShader(kind) };
 
     unsafe {
@@ -232,10 +600,14 @@ fn shader_from_source(
                 len,
                 std::ptr::null_mut(),
                 error.as_ptr() as *mut gl::types::GLchar,
-            )
+            );
+            gl.DeleteShader(id);
         }
 
-        return Err(error.to_string_lossy().into_owned());
+        return Err(ShaderError::compile(
+            kind,
+            error.to_string_lossy().into_owned(),
+        ));
     }
 
     Ok(id)
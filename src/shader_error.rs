@@ -0,0 +1,336 @@
+use gl;
+use std::fmt;
+
+// Lines shown before and after the failing one
+const EXCERPT_CONTEXT: u32 = 1;
+
+/**
+    One message from a compiler log. `file` is the source string number from the
+    log until `locate` maps it to a file name, messages without a location have
+    no `line` and no excerpt.
+**/
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    pub message: String,
+    pub excerpt: Vec<(u32, String)>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ShaderError {
+    // Source could not be handed to the driver, e.g. missing include or nul byte
+    InvalidSource {
+        path: String,
+        message: String,
+    },
+    Compile {
+        kind: gl::types::GLenum,
+        log: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+    Link {
+        log: String,
+    },
+}
+
+impl ShaderError {
+    pub fn compile(kind: gl::types::GLenum, log: String) -> Self {
+        ShaderError::Compile {
+            kind,
+            diagnostics: parse_log(&log),
+            log,
+        }
+    }
+
+    // Source string numbers to `files`, excerpts from `sources` at the same index
+    pub fn locate(mut self, files: &[String], sources: &[String]) -> Self {
+        if let ShaderError::Compile { diagnostics, .. } = &mut self {
+            for diagnostic in diagnostics.iter_mut() {
+                let index = match diagnostic.file.parse::<usize>() {
+                    Ok(index) if index < files.len() => index,
+                    _ => continue,
+                };
+
+                diagnostic.file = files[index].clone();
+
+                if let (Some(line), Some(source)) = (diagnostic.line, sources.get(index)) {
+                    diagnostic.excerpt = excerpt(source, line);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::InvalidSource { path, message } => {
+                write!(f, "invalid source {}: {}", path, message)
+            }
+            ShaderError::Compile {
+                kind,
+                log,
+                diagnostics,
+            } => {
+                writeln!(f, "{} shader failed to compile", stage_name(*kind))?;
+
+                // unknown log format, better than nothing
+                if diagnostics.iter().all(|d| d.line.is_none()) {
+                    return write!(f, "{}", log.trim_end());
+                }
+
+                for diagnostic in diagnostics {
+                    write!(f, "{}", diagnostic)?;
+                }
+
+                Ok(())
+            }
+            ShaderError::Link { log } => write!(f, "program failed to link\n{}", log.trim_end()),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => writeln!(f, "{}:{}: {}", self.file, line, self.message)?,
+            None => writeln!(f, "{}", self.message)?,
+        }
+
+        for (number, text) in &self.excerpt {
+            let marker = if Some(*number) == self.line { '>' } else { ' ' };
+            writeln!(f, "{} {:5} | {}", marker, number, text)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// Self tests and other String errors
+impl From<ShaderError> for String {
+    fn from(error: ShaderError) -> String {
+        error.to_string()
+    }
+}
+
+pub fn stage_name(kind: gl::types::GLenum) -> &'static str {
+    match kind {
+        gl::VERTEX_SHADER => "vertex",
+        gl::FRAGMENT_SHADER => "fragment",
+        gl::GEOMETRY_SHADER => "geometry",
+        gl::TESS_CONTROL_SHADER => "tessellation control",
+        gl::TESS_EVALUATION_SHADER => "tessellation evaluation",
+        gl::COMPUTE_SHADER => "compute",
+        _ => "unknown",
+    }
+}
+
+fn excerpt(source: &str, line: u32) -> Vec<(u32, String)> {
+    let first = line.saturating_sub(EXCERPT_CONTEXT).max(1);
+
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| (index as u32 + 1, text.to_string()))
+        .skip_while(|&(number, _)| number < first)
+        .take_while(|&(number, _)| number <= line + EXCERPT_CONTEXT)
+        .collect()
+}
+
+/**
+    LOG PARSING
+    "0(12) : error C0000: ..." (NVIDIA), "0:12(5): error: ..." (Mesa) and
+    "ERROR: 0:12: ..." (AMD, Intel), the first number is the source string
+**/
+
+pub fn parse_log(log: &str) -> Vec<Diagnostic> {
+    log.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match parse_location(line) {
+            Some((file, number, message)) => Diagnostic {
+                file,
+                line: Some(number),
+                message,
+                excerpt: vec![],
+            },
+            None => Diagnostic {
+                file: String::new(),
+                line: None,
+                message: line.to_string(),
+                excerpt: vec![],
+            },
+        })
+        .collect()
+}
+
+fn parse_location(line: &str) -> Option<(String, u32, String)> {
+    let bytes = line.as_bytes();
+    let digits_end = |from: usize| {
+        (from..bytes.len())
+            .find(|&i| !bytes[i].is_ascii_digit())
+            .unwrap_or(bytes.len())
+    };
+
+    let mut start = 0;
+
+    while start < bytes.len() {
+        if !bytes[start].is_ascii_digit() || (start > 0 && bytes[start - 1].is_ascii_alphanumeric())
+        {
+            start += 1;
+            continue;
+        }
+
+        let end = digits_end(start);
+
+        let followed_by_line = end + 1 < bytes.len()
+            && (bytes[end] == b':' || bytes[end] == b'(')
+            && bytes[end + 1].is_ascii_digit();
+
+        if !followed_by_line {
+            start = end;
+            continue;
+        }
+
+        let line_end = digits_end(end + 1);
+        let number = line[end + 1..line_end].parse().ok()?;
+
+        // closing paren (NVIDIA), column (Mesa), then separators
+        let mut rest = &line[line_end..];
+        if bytes[end] == b'(' {
+            rest = rest.strip_prefix(')').unwrap_or(rest);
+        }
+        if rest.starts_with('(') {
+            rest = rest.find(')').map(|i| &rest[i + 1..]).unwrap_or(rest);
+        }
+        let rest = rest.trim_start_matches([':', ' ']);
+
+        let prefix = line[..start].trim_end_matches([':', ' ']);
+        let message = if prefix.is_empty() {
+            rest.to_string()
+        } else {
+            format!("{}: {}", prefix, rest)
+        };
+
+        return Some((line[start..end].to_string(), number, message));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor;
+
+    fn location(line: &str) -> (String, u32, String) {
+        parse_location(line).unwrap_or_else(|| panic!("no location in {:?}", line))
+    }
+
+    #[test]
+    fn parses_nvidia_logs() {
+        assert_eq!(
+            location("0(12) : error C0000: syntax error, unexpected '}'"),
+            (
+                "0".to_string(),
+                12,
+                "error C0000: syntax error, unexpected '}'".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn parses_mesa_logs() {
+        assert_eq!(
+            location("1:7(15): error: `color' undeclared"),
+            ("1".to_string(), 7, "error: `color' undeclared".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_amd_and_intel_logs() {
+        assert_eq!(
+            location("ERROR: 2:30: 'vec5' : no matching overloaded function found"),
+            (
+                "2".to_string(),
+                30,
+                "ERROR: 'vec5' : no matching overloaded function found".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_lines_without_location() {
+        let diagnostics = parse_log("\nERROR: 1 compilation errors.  No code generated.\n\n");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, None);
+        assert_eq!(
+            diagnostics[0].message,
+            "ERROR: 1 compilation errors.  No code generated."
+        );
+
+        // shown as is when nothing could be located
+        let error = ShaderError::compile(gl::VERTEX_SHADER, "linker says no\n".to_string());
+        assert_eq!(
+            error.to_string(),
+            "vertex shader failed to compile\nlinker says no"
+        );
+    }
+
+    #[test]
+    fn excerpt_is_clamped_to_the_source() {
+        let source = "one\ntwo\nthree";
+
+        assert_eq!(
+            excerpt(source, 1),
+            vec![(1, "one".to_string()), (2, "two".to_string())]
+        );
+        assert_eq!(
+            excerpt(source, 3),
+            vec![(2, "two".to_string()), (3, "three".to_string())]
+        );
+    }
+
+    #[test]
+    fn locates_errors_in_included_files() {
+        let root = std::env::temp_dir().join(format!("shader_error_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("light.glsl"),
+            "float a;\nvec5 b;\nfloat c;\nfloat d;\n",
+        )
+        .unwrap();
+
+        let path = root.join("main.frag").to_string_lossy().replace('\\', "/");
+        let source = "#version 420 core\n#include \"light.glsl\"\nvoid main() {}\n";
+        let preprocessed = preprocessor::preprocess(&path, source, &[]).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        // source string 1 is the include, as the #line directives number it
+        let log = "1:2(1): error: syntax error, unexpected IDENTIFIER\n\
+                   0:3(13): error: main has no return\n";
+        let error = ShaderError::compile(gl::FRAGMENT_SHADER, log.to_string())
+            .locate(&preprocessed.files, &preprocessed.sources);
+
+        let expected = [
+            "fragment shader failed to compile".to_string(),
+            format!(
+                "{}:2: error: syntax error, unexpected IDENTIFIER",
+                preprocessed.files[1]
+            ),
+            "      1 | float a;".to_string(),
+            ">     2 | vec5 b;".to_string(),
+            "      3 | float c;".to_string(),
+            format!("{}:3: error: main has no return", path),
+            "      2 | #include \"light.glsl\"".to_string(),
+            ">     3 | void main() {}".to_string(),
+        ];
+        assert_eq!(error.to_string(), expected.join("\n") + "\n");
+    }
+}
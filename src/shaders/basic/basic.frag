@@ -1,4 +1,4 @@
-#version 330 core
+#version 420 core
 
 // Material
 struct Material {
@@ -13,16 +13,8 @@ struct Material {
 
 uniform Material material;
 
-// Light
-struct Light {
-    vec3 position;
-
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
-
-uniform Light light;
+// Camera and light
+#include "../common/frame.glsl"
 
 in VS_OUTPUT {
     vec2 TexCoords;
@@ -36,63 +28,28 @@ in VS_OUTPUT {
 out vec4 FragColor;
 
 uniform float height_scale;
-uniform vec3 viewPos;
 
-vec2 ParallaxMapping(vec2 texCoords, vec3 viewDir)
-{
-    // number of depth layers
-    const float minLayers = 8.0;
-    const float maxLayers = 32.0;
-    float numLayers = mix(maxLayers, minLayers, max(dot(vec3(0.0, 0.0, 1.0), viewDir), 0.0));
-    // calculate the size of each layer
-    float layerDepth = 1.0 / numLayers;
-    // depth of current layer
-    float currentLayerDepth = 0.0;
-    // the amount to shift the texture coordinates per layer (from vector P)
-    vec2 P = viewDir.xy  * height_scale;
-    vec2 deltaTexCoords = P / numLayers;
-
-    // get initial values
-    vec2  currentTexCoords     = texCoords;
-    float currentDepthMapValue = texture(material.texture_height1, texCoords).r;
-
-    while(currentLayerDepth < currentDepthMapValue)
-    {
-        // shift texture coordinates along direction of P
-        currentTexCoords -= deltaTexCoords;
-        // get depthmap value at current texture coordinates
-        currentDepthMapValue = texture(material.texture_height1, currentTexCoords).r;
-        // get depth of next layer
-        currentLayerDepth += layerDepth;
-    }
-
-    // get texture coordinates before collision (reverse operations)
-    vec2 prevTexCoords = currentTexCoords + deltaTexCoords;
-
-    // get depth after and before collision for linear interpolation
-    float afterDepth  = currentDepthMapValue - currentLayerDepth;
-    float beforeDepth = texture(material.texture_height1, prevTexCoords).r - currentLayerDepth + layerDepth;
-
-    // interpolation of texture coordinates
-    float weight = afterDepth / (afterDepth - beforeDepth);
-    vec2 finalTexCoords = prevTexCoords * weight + currentTexCoords * (1.0 - weight);
-
-    return finalTexCoords;
-}
+#include "../common/parallax.glsl"
 
 void main()
 {
     // TexCoords
     vec3 viewDir = normalize(IN.TangentViewPos - IN.TangentFragPos);
-//    vec2 texCoords = IN.TexCoords;
+#ifdef PARALLAX
     vec2 texCoords = ParallaxMapping(IN.TexCoords, viewDir);
 
     if(texCoords.x > 1.0 || texCoords.y > 1.0 || texCoords.x < 0.0 || texCoords.y < 0.0)
     discard;
+#else
+    vec2 texCoords = IN.TexCoords;
+#endif
 
-    // For normal mapping
+#ifdef NORMAL_MAP
     vec3 normal = texture(material.texture_normal1, texCoords).rgb;
     normal = normalize(normal * 2.0 - 1.0);
+#else
+    vec3 normal = vec3(0.0, 0.0, 1.0); // tangent space
+#endif
 
     vec4 color = texture(material.texture_diffuse1, texCoords);
 
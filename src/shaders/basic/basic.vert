@@ -1,4 +1,4 @@
-#version 330 core
+#version 420 core
 
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec3 Normal;
@@ -7,19 +7,8 @@ layout (location = 3) in vec3 Tangent;
 layout (location = 4) in vec3 Bitangent;
 
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
 
-struct Light {
-    vec3 position;
-
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
-
-uniform Light light;
-uniform vec3 viewPos;
+#include "../common/frame.glsl"
 
 out VS_OUTPUT {
     vec2 TexCoords;
@@ -0,0 +1,31 @@
+#version 420 core
+
+// Material, one array layer per block type
+struct Material {
+    sampler2DArray texture_diffuse1;
+};
+
+uniform Material material;
+uniform float layer;
+
+// Camera and light
+#include "../common/frame.glsl"
+
+in VS_OUTPUT {
+    vec2 TexCoords;
+    vec3 FragPos;
+    vec3 Normal;
+} IN;
+
+out vec4 FragColor;
+
+void main()
+{
+    vec3 color = texture(material.texture_diffuse1, vec3(IN.TexCoords, layer)).rgb;
+
+    vec3 normal = normalize(IN.Normal);
+    vec3 lightDir = normalize(light.position - IN.FragPos);
+    vec3 diffuse = light.diffuse * max(dot(normal, lightDir), 0.0);
+
+    FragColor = vec4((light.ambient + diffuse) * color, 1.0);
+}
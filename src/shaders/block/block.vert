@@ -0,0 +1,23 @@
+#version 420 core
+
+layout (location = 0) in vec3 Position;
+layout (location = 1) in vec3 Normal;
+layout (location = 2) in vec2 TexCoords;
+
+uniform mat4 model;
+
+#include "../common/frame.glsl"
+
+out VS_OUTPUT {
+    vec2 TexCoords;
+    vec3 FragPos;
+    vec3 Normal;
+} OUT;
+
+void main()
+{
+    gl_Position = projection * view * model * vec4(Position, 1.0);
+    OUT.TexCoords = TexCoords;
+    OUT.FragPos = vec3(model * vec4(Position, 1.0));
+    OUT.Normal = transpose(inverse(mat3(model))) * Normal;
+}
@@ -1,11 +1,11 @@
-#version 330 core
+#version 420 core
 
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec3 Normal;
 
 uniform mat4 model;
-uniform mat4 view;
-uniform mat4 projection;
+
+#include "../common/frame.glsl"
 
 void main()
 {
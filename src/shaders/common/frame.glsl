@@ -0,0 +1,9 @@
+// Per frame data, uniform_buffer::FrameData on the CPU side
+#include "light.glsl"
+
+layout (std140, binding = 0) uniform Frame {
+    mat4 projection;
+    mat4 view;
+    vec3 viewPos;
+    Light light;
+};
@@ -27,8 +27,10 @@ void main()
     **/
     // https://github.com/McNopper/OpenGL/blob/master/Example42/shader/fxaa.frag.glsl
 
+    //one texel of the current resolution
+    vec2 u_texelStep = 1.0 / resolution.xy;
+
     //hardcoded data
-    vec2 u_texelStep = vec2(1.0/1024.0, 1.0/600.0);
     int u_showEdges = 0;
 
     float u_lumaThreshold = 0.45;
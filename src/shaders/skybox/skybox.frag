@@ -0,0 +1,19 @@
+#version 420 core
+
+layout (binding = 0) uniform samplerCube environment;
+uniform float exposure; // tone mapped when above 0, for hdr environments
+
+in vec3 Direction;
+
+out vec4 FragColor;
+
+void main()
+{
+    vec3 color = texture(environment, Direction).rgb;
+
+    if (exposure > 0.0) {
+        color = vec3(1.0) - exp(-color * exposure);
+    }
+
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 420 core
+
+layout (location = 0) in vec3 Position;
+
+#include "../common/frame.glsl"
+
+uniform float farDepth; // window depth of the far plane, 0 with reverse-Z
+
+out vec3 Direction;
+
+void main()
+{
+    Direction = Position;
+
+    // rotation only, skybox stays around the camera
+    vec4 position = projection * mat4(mat3(view)) * vec4(Position, 1.0);
+
+    // depth is farDepth after the divide, behind everything drawn before
+    gl_Position = vec4(position.xy, farDepth * position.w, position.w);
+}
@@ -0,0 +1,186 @@
+use crate::camera::Camera;
+use crate::compute::{bind_image, dispatch, group_count, memory_barrier};
+use crate::shader::Program;
+use crate::shader_error::ShaderError;
+use crate::texture::{Texture, TextureOptions};
+use gl;
+
+// Unit cube around the camera, drawn from the inside so winding does not matter
+static CORNERS: [f32; 24] = [
+    -1., -1., -1., //
+    1., -1., -1., //
+    1., 1., -1., //
+    -1., 1., -1., //
+    -1., -1., 1., //
+    1., -1., 1., //
+    1., 1., 1., //
+    -1., 1., 1., //
+];
+
+static INDICES: [u32; 36] = [
+    0, 1, 2, 2, 3, 0, // -z
+    4, 6, 5, 6, 4, 7, // +z
+    0, 3, 7, 7, 4, 0, // -x
+    1, 5, 6, 6, 2, 1, // +x
+    0, 4, 5, 5, 1, 0, // -y
+    3, 2, 6, 6, 7, 3, // +y
+];
+
+const LOCAL_SIZE: u32 = 8;
+
+/**
+    Environment cubemap drawn at max depth after opaque geometry, so only
+    pixels nothing was drawn on are shaded. Camera comes from the frame uniforms.
+**/
+pub struct Skybox {
+    gl: gl::GlPtr,
+    pub program: Program,
+    vao: gl::types::GLuint,
+    vbo: gl::types::GLuint,
+    ebo: gl::types::GLuint,
+
+    pub exposure: f32, // 0 for LDR environments, tone mapped otherwise
+}
+
+impl Skybox {
+    pub fn new(gl: &gl::GlPtr) -> Result<Self, ShaderError> {
+        let program = Program::from_sources(
+            gl,
+            vec![
+                shader_source!(gl::VERTEX_SHADER, "shaders/skybox/skybox.vert"),
+                shader_source!(gl::FRAGMENT_SHADER, "shaders/skybox/skybox.frag"),
+            ],
+        )?;
+
+        let (mut vao, mut vbo, mut ebo) = (0, 0, 0);
+
+        unsafe {
+            gl.GenVertexArrays(1, &mut vao);
+            gl.GenBuffers(1, &mut vbo);
+            gl.GenBuffers(1, &mut ebo);
+
+            gl.BindVertexArray(vao);
+
+            gl.BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl.BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(&CORNERS) as gl::types::GLsizeiptr,
+                CORNERS.as_ptr() as *const gl::types::GLvoid,
+                gl::STATIC_DRAW,
+            );
+
+            gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl.BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                std::mem::size_of_val(&INDICES) as gl::types::GLsizeiptr,
+                INDICES.as_ptr() as *const gl::types::GLvoid,
+                gl::STATIC_DRAW,
+            );
+
+            gl.EnableVertexAttribArray(0);
+            gl.VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
+
+            gl.BindVertexArray(0);
+            gl.BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            // no visible edges between faces when filtering
+            gl.Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+
+        Ok(Self {
+            gl: gl.clone(),
+            program,
+            vao,
+            vbo,
+            ebo,
+            exposure: 0.,
+        })
+    }
+
+    // After opaque geometry, before anything blended
+    pub fn draw(&self, environment: &Texture, camera: &Camera) {
+        // far plane is at 0 with reverse-Z
+        let far_depth = camera.clear_depth() as f32;
+
+        self.program.bind();
+        self.program.set_uniform("exposure", &self.exposure);
+        self.program.set_uniform("farDepth", &far_depth);
+
+        unsafe {
+            self.gl.ActiveTexture(gl::TEXTURE0);
+            environment.bind();
+
+            // equal to the cleared depth has to pass
+            self.gl.DepthFunc(if camera.depth_func() == gl::GREATER {
+                gl::GEQUAL
+            } else {
+                gl::LEQUAL
+            });
+            self.gl.DepthMask(gl::FALSE);
+
+            self.gl.BindVertexArray(self.vao);
+            self.gl.DrawElements(
+                gl::TRIANGLES,
+                INDICES.len() as gl::types::GLsizei,
+                gl::UNSIGNED_INT,
+                std::ptr::null(),
+            );
+            self.gl.BindVertexArray(0);
+
+            self.gl.DepthMask(gl::TRUE);
+            self.gl.DepthFunc(camera.depth_func());
+        }
+    }
+}
+
+impl Drop for Skybox {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteBuffers(1, &self.ebo);
+            self.gl.DeleteBuffers(1, &self.vbo);
+            self.gl.DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+/**
+    EQUIRECTANGULAR TO CUBEMAP
+**/
+
+// Latitude-longitude image (usually .hdr) to a RGBA16F cubemap with mipmaps, on the GPU
+pub fn cubemap_from_equirect<P: AsRef<std::path::Path>>(
+    gl: &gl::GlPtr,
+    path: P,
+    size: u32,
+) -> Result<Texture, String> {
+    let equirect = Texture::load(gl, path.as_ref(), &TextureOptions::new().mipmaps(false))?;
+
+    let program = Program::from_sources(
+        gl,
+        vec![shader_source!(
+            gl::COMPUTE_SHADER,
+            "shaders/skybox/equirect_to_cube.comp"
+        )],
+    )?;
+
+    let cubemap = Texture::new_cubemap(gl, size, gl::RGBA16F, true);
+
+    unsafe {
+        gl.ActiveTexture(gl::TEXTURE0);
+    }
+    equirect.bind();
+
+    // all six faces as one imageCube
+    bind_image(gl, 0, &cubemap, gl::WRITE_ONLY, gl::RGBA16F);
+
+    let groups = group_count(size as usize, LOCAL_SIZE);
+    dispatch(gl, &program, groups, groups, 6);
+    memory_barrier(
+        gl,
+        gl::TEXTURE_FETCH_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT,
+    );
+
+    cubemap.generate_mipmaps();
+
+    Ok(cubemap)
+}
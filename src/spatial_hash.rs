@@ -0,0 +1,586 @@
+use crate::components::TransformComponent;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+type CellKey = (i32, i32, i32);
+
+// Objects or queries spanning more cells than this skip the grid
+const MAX_CELLS: i64 = 512;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: glm::Vec3, max: glm::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_center(center: &glm::Vec3, half_extents: &glm::Vec3) -> Self {
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    // Bounds of unit cube (-0.5..0.5) transformed by component, same as TransformComponent::mat4
+    pub fn from_transform(transform: &TransformComponent) -> Self {
+        let m = transform.mat4();
+        let mut half_extents = glm::vec3(0., 0., 0.);
+
+        for row in 0..3 {
+            half_extents[row] = 0.5 * (m[(row, 0)].abs() + m[(row, 1)].abs() + m[(row, 2)].abs());
+        }
+
+        Self::from_center(&transform.position, &half_extents)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && self.max[i] >= other.min[i])
+    }
+
+    pub fn intersects_sphere(&self, center: &glm::Vec3, radius: f32) -> bool {
+        let closest = glm::clamp_vec(center, &self.min, &self.max);
+        glm::distance2(&closest, center) <= radius * radius
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SpatialHandle(usize);
+
+struct Entry {
+    aabb: Aabb,
+    cells: (CellKey, CellKey), // min and max cell, inclusive
+}
+
+/**
+    Uniform grid keyed by cell coordinate, for broadphase and proximity queries
+**/
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<CellKey, Vec<SpatialHandle>>,
+    oversized: Vec<SpatialHandle>, // entries too large to bucket, checked by every query
+
+    entries: Vec<Option<Entry>>,
+    free: Vec<usize>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0., "Cell size must be positive");
+
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            oversized: vec![],
+            entries: vec![],
+            free: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn cell_of(&self, point: &glm::Vec3) -> CellKey {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+            (point.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cell_range(&self, aabb: &Aabb) -> (CellKey, CellKey) {
+        (self.cell_of(&aabb.min), self.cell_of(&aabb.max))
+    }
+
+    fn cell_count(range: (CellKey, CellKey)) -> i64 {
+        let (min, max) = range;
+        let span = |a: i32, b: i32| (i64::from(b) - i64::from(a) + 1).max(0);
+
+        span(min.0, max.0)
+            .saturating_mul(span(min.1, max.1))
+            .saturating_mul(span(min.2, max.2))
+    }
+
+    fn is_oversized(range: (CellKey, CellKey)) -> bool {
+        Self::cell_count(range) > MAX_CELLS
+    }
+
+    fn for_each_cell<F>(range: (CellKey, CellKey), mut f: F)
+    where
+        F: FnMut(CellKey),
+    {
+        let (min, max) = range;
+
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    f((x, y, z));
+                }
+            }
+        }
+    }
+
+    fn link(&mut self, handle: SpatialHandle, range: (CellKey, CellKey)) {
+        if Self::is_oversized(range) {
+            self.oversized.push(handle);
+            return;
+        }
+
+        let cells = &mut self.cells;
+        Self::for_each_cell(range, |key| {
+            cells.entry(key).or_insert_with(Vec::new).push(handle)
+        });
+    }
+
+    fn unlink(&mut self, handle: SpatialHandle, range: (CellKey, CellKey)) {
+        if Self::is_oversized(range) {
+            if let Some(i) = self.oversized.iter().position(|&h| h == handle) {
+                self.oversized.swap_remove(i);
+            }
+            return;
+        }
+
+        let cells = &mut self.cells;
+        Self::for_each_cell(range, |key| {
+            if let Some(bucket) = cells.get_mut(&key) {
+                if let Some(i) = bucket.iter().position(|&h| h == handle) {
+                    bucket.swap_remove(i);
+                }
+
+                if bucket.is_empty() {
+                    cells.remove(&key);
+                }
+            }
+        });
+    }
+
+    pub fn insert(&mut self, aabb: Aabb) -> SpatialHandle {
+        let cells = self.cell_range(&aabb);
+        let entry = Some(Entry { aabb, cells });
+
+        let handle = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = entry;
+                SpatialHandle(i)
+            }
+            None => {
+                self.entries.push(entry);
+                SpatialHandle(self.entries.len() - 1)
+            }
+        };
+
+        self.link(handle, cells);
+        handle
+    }
+
+    pub fn insert_transform(&mut self, transform: &TransformComponent) -> SpatialHandle {
+        self.insert(Aabb::from_transform(transform))
+    }
+
+    // Only touches buckets when object crosses a cell boundary
+    pub fn update(&mut self, handle: SpatialHandle, aabb: Aabb) {
+        let new_cells = self.cell_range(&aabb);
+
+        let old_cells = match self.entries.get_mut(handle.0) {
+            Some(Some(entry)) => {
+                let old_cells = entry.cells;
+                entry.aabb = aabb;
+                entry.cells = new_cells;
+                old_cells
+            }
+            _ => return,
+        };
+
+        if old_cells != new_cells {
+            self.unlink(handle, old_cells);
+            self.link(handle, new_cells);
+        }
+    }
+
+    pub fn update_transform(&mut self, handle: SpatialHandle, transform: &TransformComponent) {
+        self.update(handle, Aabb::from_transform(transform));
+    }
+
+    pub fn remove(&mut self, handle: SpatialHandle) -> Option<Aabb> {
+        let entry = self.entries.get_mut(handle.0)?.take()?;
+
+        self.unlink(handle, entry.cells);
+        self.free.push(handle.0);
+
+        Some(entry.aabb)
+    }
+
+    pub fn get(&self, handle: SpatialHandle) -> Option<&Aabb> {
+        self.entries
+            .get(handle.0)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| &entry.aabb)
+    }
+
+    // Visits every entry that may touch `range`, scanning all entries when the range is too large
+    fn for_each_candidate<F>(&self, range: (CellKey, CellKey), mut f: F)
+    where
+        F: FnMut(SpatialHandle, &Aabb),
+    {
+        if Self::is_oversized(range) {
+            for (i, entry) in self.entries.iter().enumerate() {
+                if let Some(entry) = entry {
+                    f(SpatialHandle(i), &entry.aabb);
+                }
+            }
+            return;
+        }
+
+        Self::for_each_cell(range, |key| {
+            if let Some(bucket) = self.cells.get(&key) {
+                for &handle in bucket {
+                    f(handle, self.get(handle).unwrap());
+                }
+            }
+        });
+
+        for &handle in &self.oversized {
+            f(handle, self.get(handle).unwrap());
+        }
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<SpatialHandle> {
+        let mut found = HashSet::new();
+
+        self.for_each_candidate(self.cell_range(aabb), |handle, other| {
+            if other.intersects(aabb) {
+                found.insert(handle);
+            }
+        });
+
+        found.into_iter().collect()
+    }
+
+    pub fn query_radius(&self, center: &glm::Vec3, radius: f32) -> Vec<SpatialHandle> {
+        let extents = glm::vec3(radius, radius, radius);
+        let bounds = Aabb::from_center(center, &extents);
+        let mut found = HashSet::new();
+
+        self.for_each_candidate(self.cell_range(&bounds), |handle, other| {
+            if other.intersects_sphere(center, radius) {
+                found.insert(handle);
+            }
+        });
+
+        found.into_iter().collect()
+    }
+
+    // Broadphase: every overlapping pair once, lower handle first
+    pub fn pairs(&self) -> Vec<(SpatialHandle, SpatialHandle)> {
+        let mut found = HashSet::new();
+
+        for bucket in self.cells.values() {
+            for (i, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[i + 1..] {
+                    let pair = if a.0 < b.0 { (a, b) } else { (b, a) };
+
+                    if found.contains(&pair) {
+                        continue;
+                    }
+
+                    if self.get(a).unwrap().intersects(self.get(b).unwrap()) {
+                        found.insert(pair);
+                    }
+                }
+            }
+        }
+
+        // Oversized entries are not in any bucket, test them against everything
+        for &a in &self.oversized {
+            let aabb = self.get(a).unwrap();
+
+            for (i, entry) in self.entries.iter().enumerate() {
+                let b = SpatialHandle(i);
+
+                match entry {
+                    Some(entry) if b != a && entry.aabb.intersects(aabb) => {
+                        found.insert(if a.0 < b.0 { (a, b) } else { (b, a) });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        found.into_iter().collect()
+    }
+}
+
+/**
+    BENCHMARK
+**/
+
+fn brute_force_pairs(aabbs: &[Aabb]) -> usize {
+    let mut count = 0;
+
+    for i in 0..aabbs.len() {
+        for j in i + 1..aabbs.len() {
+            if aabbs[i].intersects(&aabbs[j]) {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+// Moves `objects` boxes around a cube of `world_size` and compares pair enumeration with brute force
+pub fn benchmark(objects: usize, steps: usize, world_size: f32) {
+    // xorshift, good enough for scattering boxes
+    let mut seed: u32 = 0x9E37_79B9;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as f32 / std::u32::MAX as f32
+    };
+
+    let half_extents = glm::vec3(0.5, 0.5, 0.5);
+    let mut positions: Vec<glm::Vec3> = (0..objects)
+        .map(|_| glm::vec3(random(), random(), random()) * world_size)
+        .collect();
+    let velocities: Vec<glm::Vec3> = (0..objects)
+        .map(|_| (glm::vec3(random(), random(), random()) - glm::vec3(0.5, 0.5, 0.5)) * 0.2)
+        .collect();
+
+    let mut grid = SpatialHash::new(2.0);
+    let handles: Vec<SpatialHandle> = positions
+        .iter()
+        .map(|p| grid.insert(Aabb::from_center(p, &half_extents)))
+        .collect();
+
+    let mut grid_time = 0.;
+    let mut brute_time = 0.;
+    let mut grid_pairs = 0;
+    let mut brute_pairs = 0;
+
+    for _ in 0..steps {
+        for (p, v) in positions.iter_mut().zip(&velocities) {
+            *p += v;
+
+            for i in 0..3 {
+                p[i] = p[i].rem_euclid(world_size);
+            }
+        }
+
+        let aabbs: Vec<Aabb> = positions
+            .iter()
+            .map(|p| Aabb::from_center(p, &half_extents))
+            .collect();
+
+        let start = Instant::now();
+        for (&handle, aabb) in handles.iter().zip(&aabbs) {
+            grid.update(handle, *aabb);
+        }
+        grid_pairs += grid.pairs().len();
+        grid_time += start.elapsed().as_secs_f32();
+
+        let start = Instant::now();
+        brute_pairs += brute_force_pairs(&aabbs);
+        brute_time += start.elapsed().as_secs_f32();
+    }
+
+    println!(
+        "Spatial hash: {} objects, {} steps | grid {:.2} ms/step ({} pairs) | brute force {:.2} ms/step ({} pairs)",
+        objects,
+        steps,
+        grid_time * 1000. / steps as f32,
+        grid_pairs,
+        brute_time * 1000. / steps as f32,
+        brute_pairs,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::from_center(&glm::vec3(x, y, z), &glm::vec3(0.5, 0.5, 0.5))
+    }
+
+    fn sorted(mut handles: Vec<SpatialHandle>) -> Vec<SpatialHandle> {
+        handles.sort_by_key(|h| h.0);
+        handles
+    }
+
+    fn sorted_pairs(
+        mut pairs: Vec<(SpatialHandle, SpatialHandle)>,
+    ) -> Vec<(SpatialHandle, SpatialHandle)> {
+        pairs.sort_by_key(|(a, b)| (a.0, b.0));
+        pairs
+    }
+
+    #[test]
+    fn insert_and_query() {
+        let mut grid = SpatialHash::new(2.0);
+        let a = grid.insert(unit_box(1., 1., 1.));
+        let b = grid.insert(unit_box(10., 1., 1.));
+
+        assert_eq!(grid.len(), 2);
+        assert_eq!(grid.query_aabb(&unit_box(1.5, 1., 1.)), vec![a]);
+        assert_eq!(grid.query_radius(&glm::vec3(10., 3., 1.), 1.6), vec![b]);
+        assert!(grid.query_radius(&glm::vec3(10., 3., 1.), 1.4).is_empty());
+        assert!(grid.query_aabb(&unit_box(5., 5., 5.)).is_empty());
+    }
+
+    #[test]
+    fn straddling_objects_are_found_from_every_cell() {
+        let mut grid = SpatialHash::new(2.0);
+        // Spans cells -1 and 0 on every axis
+        let a = grid.insert(unit_box(0., 0., 0.));
+
+        for &corner in &[-0.4, 0.4] {
+            let found = grid.query_aabb(&Aabb::from_center(
+                &glm::vec3(corner, corner, corner),
+                &glm::vec3(0.01, 0.01, 0.01),
+            ));
+            assert_eq!(found, vec![a]);
+        }
+
+        // Overlaps in several shared cells, still reported once
+        let b = grid.insert(unit_box(0.5, 0.5, 0.5));
+        assert_eq!(grid.pairs(), vec![(a, b)]);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let mut grid = SpatialHash::new(2.0);
+        assert_eq!(grid.cell_of(&glm::vec3(-0.1, -2.0, -2.1)), (-1, -1, -2));
+
+        let a = grid.insert(unit_box(-5., -5., -5.));
+        let b = grid.insert(unit_box(5., 5., 5.));
+
+        assert_eq!(grid.query_aabb(&unit_box(-5.5, -5., -4.5)), vec![a]);
+        assert_eq!(grid.query_radius(&glm::vec3(5., 5., 5.), 0.1), vec![b]);
+        assert!(grid.pairs().is_empty());
+    }
+
+    #[test]
+    fn update_moves_between_cells() {
+        let mut grid = SpatialHash::new(2.0);
+        let a = grid.insert(unit_box(1., 1., 1.));
+        let b = grid.insert(unit_box(-7., 1., 1.));
+        assert!(grid.pairs().is_empty());
+
+        grid.update(a, unit_box(-6.5, 1., 1.));
+        assert_eq!(grid.pairs(), vec![(a, b)]);
+        assert!(grid.query_aabb(&unit_box(1., 1., 1.)).is_empty());
+        assert_eq!(sorted(grid.query_aabb(&unit_box(-7., 1., 1.))), vec![a, b]);
+
+        // Old buckets are released
+        grid.update(a, unit_box(1., 1., 1.));
+        grid.remove(b);
+        assert_eq!(grid.cells.len(), 1);
+    }
+
+    #[test]
+    fn remove_frees_handle_and_buckets() {
+        let mut grid = SpatialHash::new(2.0);
+        let a = grid.insert(unit_box(0., 0., 0.));
+        let b = grid.insert(unit_box(0.5, 0., 0.));
+
+        assert_eq!(
+            grid.remove(a).map(|aabb| aabb.min),
+            Some(glm::vec3(-0.5, -0.5, -0.5))
+        );
+        assert!(grid.remove(a).is_none());
+        assert!(grid.get(a).is_none());
+        assert_eq!(grid.query_aabb(&unit_box(0., 0., 0.)), vec![b]);
+        assert!(grid.pairs().is_empty());
+
+        // Freed slot is reused
+        let c = grid.insert(unit_box(20., 0., 0.));
+        assert_eq!(c, a);
+        assert_eq!(grid.len(), 2);
+
+        grid.remove(b);
+        grid.remove(c);
+        assert!(grid.is_empty());
+        assert!(grid.cells.is_empty());
+    }
+
+    #[test]
+    fn pairs_match_brute_force() {
+        let mut grid = SpatialHash::new(1.5);
+        let mut aabbs = vec![];
+
+        for i in 0..6 {
+            for j in 0..6 {
+                let position = glm::vec3(
+                    i as f32 * 0.8 - 2.,
+                    j as f32 * 1.3 - 3.,
+                    (i * j) as f32 * -0.4,
+                );
+                aabbs.push(unit_box(position.x, position.y, position.z));
+            }
+        }
+
+        for aabb in &aabbs {
+            grid.insert(*aabb);
+        }
+
+        let mut expected = vec![];
+        for i in 0..aabbs.len() {
+            for j in i + 1..aabbs.len() {
+                if aabbs[i].intersects(&aabbs[j]) {
+                    expected.push((SpatialHandle(i), SpatialHandle(j)));
+                }
+            }
+        }
+
+        assert_eq!(expected.len(), brute_force_pairs(&aabbs));
+        assert_eq!(sorted_pairs(grid.pairs()), expected);
+    }
+
+    #[test]
+    fn oversized_objects_skip_the_grid() {
+        let mut grid = SpatialHash::new(1.0);
+        let small = grid.insert(unit_box(3., 3., 3.));
+        let far = grid.insert(unit_box(5000., 0., 0.));
+        let huge = grid.insert(Aabb::new(
+            glm::vec3(-1e6, -1e6, -1e6),
+            glm::vec3(1e6, 1e6, 1e6),
+        ));
+
+        // Unit boxes straddle 8 cells each, the huge one none
+        assert_eq!(grid.oversized, vec![huge]);
+        assert_eq!(grid.cells.len(), 16);
+
+        assert_eq!(
+            sorted(grid.query_aabb(&unit_box(3., 3., 3.))),
+            vec![small, huge]
+        );
+        assert_eq!(
+            sorted(grid.query_radius(&glm::vec3(0., 0., 0.), 1.)),
+            vec![huge]
+        );
+        assert_eq!(sorted_pairs(grid.pairs()), vec![(small, huge), (far, huge)]);
+
+        // Oversized queries fall back to a scan
+        let everything = Aabb::new(glm::vec3(-1e9, -1e9, -1e9), glm::vec3(1e9, 1e9, 1e9));
+        assert_eq!(sorted(grid.query_aabb(&everything)), vec![small, far, huge]);
+
+        // Shrinking moves it back into buckets
+        grid.update(huge, unit_box(3.5, 3., 3.));
+        assert!(grid.oversized.is_empty());
+        assert_eq!(sorted_pairs(grid.pairs()), vec![(small, huge)]);
+
+        grid.update(huge, everything);
+        grid.remove(huge);
+        assert!(grid.oversized.is_empty());
+        assert_eq!(grid.cells.len(), 16);
+    }
+}
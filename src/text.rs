@@ -35,7 +35,7 @@ impl Font {
             include_str!("shaders/text/text.vert"),
             include_str!("shaders/text/text.frag"),
         )
-        .unwrap();
+        .unwrap_or_else(|error| panic!("{}", error));
 
         let (vao, vbo) = Self::setup_vbo(&gl);
 
@@ -1,11 +1,14 @@
 extern crate stb_image;
 
+use crate::compressed::{self, CompressedImage};
 use gl;
 #[allow(unused_imports)]
 use stb_image::image::LoadResult;
 #[allow(unused_imports)]
 use stb_image::stb_image::bindgen::stbi_load_from_file;
 
+use std::fmt;
+
 type Path = std::ffi::OsStr;
 
 static mut MAX_TEXTURE_FILTERING: f32 = 0.;
@@ -29,9 +32,409 @@ impl TextureKind {
     }
 }
 
+// Color maps are authored in sRGB, data like normals and heights is linear
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    Decode { path: String, reason: String },
+    InvalidPath(String),
+    InvalidSize { path: String, reason: String },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::Decode { path, reason } => write!(f, "cannot load {}: {}", path, reason),
+            TextureError::InvalidPath(path) => write!(f, "invalid texture path {}", path),
+            TextureError::InvalidSize { path, reason } => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<TextureError> for String {
+    fn from(error: TextureError) -> String {
+        error.to_string()
+    }
+}
+
+/**
+    LOADER OPTIONS
+    TextureOptions::new().srgb().wrap(gl::CLAMP_TO_EDGE).max_size(1024)
+**/
+
+#[derive(Clone, Debug)]
+pub struct TextureOptions {
+    pub mipmaps: bool,
+    pub wrap: gl::types::GLenum,
+    pub filter: Filter,
+    pub anisotropy: Option<f32>, // None for the maximum the driver supports
+    pub flip_y: bool,
+    pub color_space: ColorSpace,
+    pub max_size: Option<u32>, // larger images are halved until they fit
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            mipmaps: true,
+            wrap: gl::REPEAT,
+            filter: Filter::Linear,
+            anisotropy: None,
+            flip_y: false,
+            color_space: ColorSpace::Linear,
+            max_size: None,
+        }
+    }
+}
+
+impl TextureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    pub fn wrap(mut self, wrap: gl::types::GLenum) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+
+    pub fn flip_y(mut self) -> Self {
+        self.flip_y = true;
+        self
+    }
+
+    pub fn srgb(mut self) -> Self {
+        self.color_space = ColorSpace::Srgb;
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    fn min_filter(&self) -> gl::types::GLenum {
+        match (self.filter, self.mipmaps) {
+            (Filter::Nearest, false) => gl::NEAREST,
+            (Filter::Linear, false) => gl::LINEAR,
+            (Filter::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    fn mag_filter(&self) -> gl::types::GLenum {
+        match self.filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        }
+    }
+}
+
+// Queried in Texture::init
+pub fn max_anisotropy() -> f32 {
+    unsafe { MAX_TEXTURE_FILTERING.max(1.) }
+}
+
+/**
+    DECODED IMAGE
+**/
+
+pub enum PixelData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>), // .hdr
+}
+
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u32, // 1 gray, 2 gray alpha, 3 rgb, 4 rgba
+    pub data: PixelData,
+}
+
+impl Image {
+    // 8-bit, 16-bit png and float hdr files, channels as stored in the file
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Image, TextureError> {
+        let path = path.as_ref();
+
+        // stb_image needs a C string
+        match path.to_str() {
+            Some(text) if !text.contains('\0') => {}
+            _ => {
+                return Err(TextureError::InvalidPath(
+                    path.to_string_lossy().into_owned(),
+                ))
+            }
+        }
+
+        if let Some(image) = Self::load_16(path) {
+            return Ok(image);
+        }
+
+        match stb_image::image::load(path) {
+            LoadResult::Error(reason) => Err(TextureError::Decode {
+                path: path.to_string_lossy().into_owned(),
+                reason,
+            }),
+            LoadResult::ImageU8(image) => Ok(Image {
+                width: image.width as u32,
+                height: image.height as u32,
+                channels: image.depth as u32,
+                data: PixelData::U8(image.data),
+            }),
+            LoadResult::ImageF32(image) => Ok(Image {
+                width: image.width as u32,
+                height: image.height as u32,
+                channels: image.depth as u32,
+                data: PixelData::F32(image.data),
+            }),
+        }
+    }
+
+    // Flipped and downscaled as the options ask
+    pub fn load_with<P: AsRef<std::path::Path>>(
+        path: P,
+        options: &TextureOptions,
+    ) -> Result<Image, TextureError> {
+        let mut image = Self::load(path)?;
+
+        if options.flip_y {
+            image.flip_vertically();
+        }
+
+        if let Some(max_size) = options.max_size {
+            while image.width.max(image.height) > max_size.max(1) {
+                image.half();
+            }
+        }
+
+        Ok(image)
+    }
+
+    // stb_image::image::load would convert 16-bit images to 8-bit, so 16-bit
+    // pngs go through the png crate. None for anything else
+    fn load_16(path: &std::path::Path) -> Option<Image> {
+        let file = std::fs::File::open(path).ok()?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::IDENTITY);
+
+        let (info, mut reader) = decoder.read_info().ok()?;
+
+        if info.bit_depth != png::BitDepth::Sixteen {
+            return None;
+        }
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::RGB => 3,
+            png::ColorType::RGBA => 4,
+            png::ColorType::Indexed => return None, // never 16-bit
+        };
+
+        let mut bytes = vec![0; info.buffer_size()];
+        reader.next_frame(&mut bytes).ok()?;
+
+        // png stores samples big-endian
+        let data = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+
+        Some(Image {
+            width: info.width,
+            height: info.height,
+            channels,
+            data: PixelData::U16(data),
+        })
+    }
+
+    // (internal format, format, type) for TexImage2D
+    pub fn gl_formats(
+        &self,
+        color_space: ColorSpace,
+    ) -> (gl::types::GLenum, gl::types::GLenum, gl::types::GLenum) {
+        let format = match self.channels {
+            1 => gl::RED,
+            2 => gl::RG,
+            3 => gl::RGB,
+            _ => gl::RGBA,
+        };
+
+        // there are no 16-bit or float sRGB formats, those are linear anyway
+        let srgb = color_space == ColorSpace::Srgb;
+
+        let (internal, kind) = match (&self.data, self.channels) {
+            (PixelData::U8(_), 1) => (gl::R8, gl::UNSIGNED_BYTE),
+            (PixelData::U8(_), 2) => (gl::RG8, gl::UNSIGNED_BYTE),
+            (PixelData::U8(_), 3) if srgb => (gl::SRGB8, gl::UNSIGNED_BYTE),
+            (PixelData::U8(_), 3) => (gl::RGB8, gl::UNSIGNED_BYTE),
+            (PixelData::U8(_), _) if srgb => (gl::SRGB8_ALPHA8, gl::UNSIGNED_BYTE),
+            (PixelData::U8(_), _) => (gl::RGBA8, gl::UNSIGNED_BYTE),
+            (PixelData::U16(_), 1) => (gl::R16, gl::UNSIGNED_SHORT),
+            (PixelData::U16(_), 2) => (gl::RG16, gl::UNSIGNED_SHORT),
+            (PixelData::U16(_), 3) => (gl::RGB16, gl::UNSIGNED_SHORT),
+            (PixelData::U16(_), _) => (gl::RGBA16, gl::UNSIGNED_SHORT),
+            // keep full precision, data textures (heightmaps, lookup tables) need it
+            (PixelData::F32(_), 1) => (gl::R32F, gl::FLOAT),
+            (PixelData::F32(_), 2) => (gl::RG32F, gl::FLOAT),
+            (PixelData::F32(_), 3) => (gl::RGB32F, gl::FLOAT),
+            (PixelData::F32(_), _) => (gl::RGBA32F, gl::FLOAT),
+        };
+
+        (internal, format, kind)
+    }
+
+    fn bytes_per_channel(&self) -> u32 {
+        match &self.data {
+            PixelData::U8(_) => 1,
+            PixelData::U16(_) => 2,
+            PixelData::F32(_) => 4,
+        }
+    }
+
+    // UNPACK_ALIGNMENT for rows of this image, default of 4 breaks odd widths of RGB or gray
+    pub fn row_alignment(&self) -> i32 {
+        let row = self.width * self.channels * self.bytes_per_channel();
+
+        match row {
+            row if row % 8 == 0 => 8,
+            row if row % 4 == 0 => 4,
+            row if row % 2 == 0 => 2,
+            _ => 1,
+        }
+    }
+
+    // stb_image flips globally, this only touches this image
+    pub fn flip_vertically(&mut self) {
+        let row = (self.width * self.channels) as usize;
+        let height = self.height as usize;
+
+        fn flip<T>(data: &mut [T], row: usize, height: usize) {
+            for y in 0..height / 2 {
+                let (top, bottom) = data.split_at_mut((height - 1 - y) * row);
+                top[y * row..(y + 1) * row].swap_with_slice(&mut bottom[..row]);
+            }
+        }
+
+        match &mut self.data {
+            PixelData::U8(data) => flip(data, row, height),
+            PixelData::U16(data) => flip(data, row, height),
+            PixelData::F32(data) => flip(data, row, height),
+        }
+    }
+
+    // 2x2 box filter, odd last row and column are dropped
+    pub fn half(&mut self) {
+        let (width, height, channels) = (
+            (self.width / 2).max(1) as usize,
+            (self.height / 2).max(1) as usize,
+            self.channels as usize,
+        );
+        let (source_width, source_height) = (self.width as usize, self.height as usize);
+
+        let average = |sample: &dyn Fn(usize) -> f32, x: usize, y: usize, c: usize| {
+            let (x0, y0) = (x * 2, y * 2);
+            let (x1, y1) = (
+                (x0 + 1).min(source_width - 1),
+                (y0 + 1).min(source_height - 1),
+            );
+
+            (sample((y0 * source_width + x0) * channels + c)
+                + sample((y0 * source_width + x1) * channels + c)
+                + sample((y1 * source_width + x0) * channels + c)
+                + sample((y1 * source_width + x1) * channels + c))
+                / 4.
+        };
+
+        let halved = |sample: &dyn Fn(usize) -> f32| -> Vec<f32> {
+            let mut data = Vec::with_capacity(width * height * channels);
+            for y in 0..height {
+                for x in 0..width {
+                    for c in 0..channels {
+                        data.push(average(sample, x, y, c));
+                    }
+                }
+            }
+            data
+        };
+
+        self.data = match &self.data {
+            PixelData::U8(data) => PixelData::U8(
+                halved(&|i| data[i] as f32)
+                    .into_iter()
+                    .map(|v| v.round() as u8)
+                    .collect(),
+            ),
+            PixelData::U16(data) => PixelData::U16(
+                halved(&|i| data[i] as f32)
+                    .into_iter()
+                    .map(|v| v.round() as u16)
+                    .collect(),
+            ),
+            PixelData::F32(data) => PixelData::F32(halved(&|i| data[i])),
+        };
+
+        self.width = width as u32;
+        self.height = height as u32;
+    }
+
+    pub fn pixels(&self) -> *const gl::types::GLvoid {
+        match &self.data {
+            PixelData::U8(data) => data.as_ptr() as *const gl::types::GLvoid,
+            PixelData::U16(data) => data.as_ptr() as *const gl::types::GLvoid,
+            PixelData::F32(data) => data.as_ptr() as *const gl::types::GLvoid,
+        }
+    }
+}
+
+// Gray images sample as gray instead of red, gray alpha as gray with alpha
+fn swizzle(channels: u32) -> Option<[gl::types::GLint; 4]> {
+    let (r, g, one) = (gl::RED as i32, gl::GREEN as i32, gl::ONE as i32);
+
+    match channels {
+        1 => Some([r, r, r, one]),
+        2 => Some([r, r, r, g]),
+        _ => None,
+    }
+}
+
 pub struct Texture {
     gl: gl::GlPtr,
     pub id: u32,
+    pub target: gl::types::GLenum, // TEXTURE_2D, TEXTURE_CUBE_MAP...
 }
 
 impl Texture {
@@ -44,115 +447,684 @@ impl Texture {
         }
     }
 
-    pub fn new(gl: &gl::GlPtr, width: u32, height: u32) -> Texture {
-        let mut texture_id: u32 = 0;
+    // Empty storage for render targets, multisampled when samples > 1
+    pub fn new_attachment(
+        gl: &gl::GlPtr,
+        internal_format: gl::types::GLenum,
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> Texture {
+        let target = if samples > 1 {
+            gl::TEXTURE_2D_MULTISAMPLE
+        } else {
+            gl::TEXTURE_2D
+        };
+        let texture = Texture::generate(gl, target);
+
+        if samples <= 1 {
+            // no mip chain, would be incomplete with the default min filter
+            unsafe {
+                gl.BindTexture(target, texture.id);
+                gl.TexParameteri(
+                    target,
+                    gl::TEXTURE_MIN_FILTER,
+                    gl::LINEAR as gl::types::GLint,
+                );
+                gl.TexParameteri(target, gl::TEXTURE_MAX_LEVEL, 0);
+                gl.BindTexture(target, 0);
+            }
+        }
+
+        texture.resize_attachment(internal_format, width, height, samples);
+        texture
+    }
 
+    // Reallocates storage, id stays the same so attachments and references remain valid
+    pub fn resize_attachment(
+        &self,
+        internal_format: gl::types::GLenum,
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) {
         unsafe {
-            gl.GenTextures(1, &mut texture_id);
-            gl.BindTexture(gl::TEXTURE_2D, texture_id);
-            gl.TexImage2D(
+            self.gl.BindTexture(self.target, self.id);
+
+            if self.target == gl::TEXTURE_2D_MULTISAMPLE {
+                self.gl.TexImage2DMultisample(
+                    gl::TEXTURE_2D_MULTISAMPLE,
+                    samples as gl::types::GLsizei,
+                    internal_format,
+                    width as gl::types::GLint,
+                    height as gl::types::GLint,
+                    gl::TRUE,
+                );
+            } else {
+                // no data, format and type only have to be compatible with the internal format
+                let (format, kind) = match internal_format {
+                    gl::DEPTH24_STENCIL8 => (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+                    gl::DEPTH32F_STENCIL8 => {
+                        (gl::DEPTH_STENCIL, gl::FLOAT_32_UNSIGNED_INT_24_8_REV)
+                    }
+                    gl::DEPTH_COMPONENT16
+                    | gl::DEPTH_COMPONENT24
+                    | gl::DEPTH_COMPONENT32
+                    | gl::DEPTH_COMPONENT32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+                    _ => (gl::RGBA, gl::UNSIGNED_BYTE),
+                };
+
+                self.gl.TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    internal_format as gl::types::GLint,
+                    width as gl::types::GLint,
+                    height as gl::types::GLint,
+                    0,
+                    format,
+                    kind,
+                    std::ptr::null() as *const gl::types::GLvoid,
+                );
+            }
+
+            self.gl.BindTexture(self.target, 0);
+        }
+    }
+
+    // Default options, linear with mipmaps
+    pub fn from<P>(gl: &gl::GlPtr, path: P) -> Result<Texture, TextureError>
+    where
+        P: AsRef<Path> + std::convert::AsRef<std::path::Path>,
+    {
+        Self::load(gl, path, &TextureOptions::default())
+    }
+
+    pub fn load<P>(
+        gl: &gl::GlPtr,
+        path: P,
+        options: &TextureOptions,
+    ) -> Result<Texture, TextureError>
+    where
+        P: AsRef<Path> + std::convert::AsRef<std::path::Path>,
+    {
+        let path: &std::path::Path = path.as_ref();
+
+        // .dds and .ktx2 keep their blocks and mip chain, flip_y does not apply
+        if compressed::is_container(path) {
+            let mut image = CompressedImage::load(path)?;
+
+            if let Some(max_size) = options.max_size {
+                image.limit_size(max_size);
+            }
+
+            return Self::from_compressed(gl, &image, options);
+        }
+
+        let image = Image::load_with(path, options)?;
+        Ok(Self::from_image(gl, &image, options))
+    }
+
+    // Uploads the blocks when the driver supports the format, decodes to RGBA8 otherwise
+    pub fn from_compressed(
+        gl: &gl::GlPtr,
+        image: &CompressedImage,
+        options: &TextureOptions,
+    ) -> Result<Texture, TextureError> {
+        let srgb = image.srgb || options.color_space == ColorSpace::Srgb;
+        let format = image.format.gl_format(srgb);
+        let native = compressed::is_supported(gl, format);
+        let texture = Self::generate(gl, gl::TEXTURE_2D);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_2D, texture.id);
+        }
+
+        for (level, data) in image.levels.iter().enumerate() {
+            let (width, height) = image.level_dimensions(level);
+
+            if native {
+                unsafe {
+                    gl.CompressedTexImage2D(
+                        gl::TEXTURE_2D,
+                        level as i32,
+                        format,
+                        width as i32,
+                        height as i32,
+                        0,
+                        data.len() as i32,
+                        data.as_ptr() as *const gl::types::GLvoid,
+                    );
+                }
+            } else {
+                let pixels = image
+                    .decode_level(level)
+                    .map_err(|reason| TextureError::Decode {
+                        path: format!("{:?} level {}", image.format, level),
+                        reason,
+                    })?;
+                let decoded = Image {
+                    width,
+                    height,
+                    channels: 4,
+                    data: PixelData::U8(pixels),
+                };
+                let internal = if srgb { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+
+                unsafe {
+                    gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+                    gl.TexImage2D(
+                        gl::TEXTURE_2D,
+                        level as i32,
+                        internal as i32,
+                        width as i32,
+                        height as i32,
+                        0,
+                        gl::RGBA,
+                        gl::UNSIGNED_BYTE,
+                        decoded.pixels(),
+                    );
+                }
+            }
+        }
+
+        // mip chain comes from the file, compressed levels cannot be generated
+        let has_mipmaps = image.levels.len() > 1;
+        apply_options(gl, gl::TEXTURE_2D, 4, &options.clone().mipmaps(false));
+
+        unsafe {
+            gl.TexParameteri(
                 gl::TEXTURE_2D,
-                0,
-                gl::RGB as gl::types::GLint,
-                width as gl::types::GLint,
-                height as gl::types::GLint,
-                0,
-                gl::RGB,
-                gl::UNSIGNED_BYTE,
-                std::ptr::null() as *const gl::types::GLvoid,
+                gl::TEXTURE_MAX_LEVEL,
+                image.levels.len() as i32 - 1,
             );
-            // gl.GenerateMipmap(gl::TEXTURE_2D);
+
+            if has_mipmaps && options.mipmaps {
+                gl.TexParameteri(
+                    gl::TEXTURE_2D,
+                    gl::TEXTURE_MIN_FILTER,
+                    options.min_filter() as i32,
+                );
+            }
 
             gl.BindTexture(gl::TEXTURE_2D, 0);
         }
 
-        Texture {
-            gl: gl.clone(),
-            id: texture_id,
+        Ok(texture)
+    }
+
+    pub fn from_image(gl: &gl::GlPtr, image: &Image, options: &TextureOptions) -> Texture {
+        let texture = Self::generate(gl, gl::TEXTURE_2D);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_2D, texture.id);
         }
+
+        upload(gl, gl::TEXTURE_2D, image, options);
+        apply_options(gl, gl::TEXTURE_2D, image.channels, options);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+        }
+
+        texture
     }
 
-    pub fn new_multisampled(gl: &gl::GlPtr, width: u32, height: u32, samples: u32) -> Texture {
+    fn generate(gl: &gl::GlPtr, target: gl::types::GLenum) -> Texture {
         let mut texture_id: u32 = 0;
 
         unsafe {
             gl.GenTextures(1, &mut texture_id);
-            gl.BindTexture(gl::TEXTURE_2D_MULTISAMPLE, texture_id);
-
-            gl.TexImage2DMultisample(
-                gl::TEXTURE_2D_MULTISAMPLE,
-                samples as gl::types::GLsizei,
-                gl::RGB,
-                width as gl::types::GLint,
-                height as gl::types::GLint,
-                gl::TRUE,
-            );
-
-            gl.BindTexture(gl::TEXTURE_2D_MULTISAMPLE, 0);
         }
 
         Texture {
             gl: gl.clone(),
             id: texture_id,
+            target,
         }
     }
 
-    pub fn from<P>(gl: &gl::GlPtr, path: P) -> Option<Texture>
+    /**
+        CUBEMAPS
+    **/
+
+    // Faces in GL order: +X, -X, +Y, -Y, +Z, -Z, all square and the same size
+    pub fn cubemap_from_files<P>(
+        gl: &gl::GlPtr,
+        paths: &[P; 6],
+        options: &TextureOptions,
+    ) -> Result<Texture, TextureError>
     where
-        P: AsRef<Path> + std::convert::AsRef<std::path::Path>,
+        P: AsRef<std::path::Path>,
     {
-        let texture_load_result = stb_image::image::load(path);
-        let mut texture_id: u32 = 0;
+        let mut faces = vec![];
 
-        match texture_load_result {
-            LoadResult::Error(_) => None,
-            LoadResult::ImageU8(image) => {
-                let texture_image = image;
-                let mode = match &texture_image.depth {
-                    3 => gl::RGB,
-                    4 => gl::RGBA,
-                    _ => gl::RGB,
-                };
+        for path in paths {
+            let image = Image::load_with(path, options)?;
+            let expected = faces
+                .first()
+                .map(|face: &Image| face.width)
+                .unwrap_or(image.width);
 
-                unsafe {
-                    gl.GenTextures(1, &mut texture_id);
-                    gl.BindTexture(gl::TEXTURE_2D, texture_id);
-                    gl.TexImage2D(
-                        gl::TEXTURE_2D,
-                        0,
-                        mode as i32,
-                        texture_image.width as i32,
-                        texture_image.height as i32,
-                        0,
-                        mode,
-                        gl::UNSIGNED_BYTE,
-                        texture_image.data.as_ptr() as *const gl::types::GLvoid,
-                    );
-                    gl.GenerateMipmap(gl::TEXTURE_2D);
+            if image.width != image.height || image.width != expected {
+                return Err(TextureError::InvalidSize {
+                    path: path.as_ref().to_string_lossy().into_owned(),
+                    reason: format!(
+                        "face is {}x{}, expected {}x{}",
+                        image.width, image.height, expected, expected
+                    ),
+                });
+            }
 
-                    gl.BindTexture(gl::TEXTURE_2D, 0);
-                }
+            faces.push(image);
+        }
 
-                Some(Texture {
-                    gl: gl.clone(),
-                    id: texture_id,
+        let texture = Self::generate(gl, gl::TEXTURE_CUBE_MAP);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_CUBE_MAP, texture.id);
+        }
+
+        for (face, image) in faces.iter().enumerate() {
+            upload(
+                gl,
+                gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32,
+                image,
+                options,
+            );
+        }
+
+        apply_options(gl, gl::TEXTURE_CUBE_MAP, faces[0].channels, options);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+
+        Ok(texture)
+    }
+
+    // Immutable storage to render or write images into, e.g. skybox::cubemap_from_equirect
+    pub fn new_cubemap(
+        gl: &gl::GlPtr,
+        size: u32,
+        internal_format: gl::types::GLenum,
+        mipmaps: bool,
+    ) -> Texture {
+        let levels = if mipmaps {
+            32 - size.max(1).leading_zeros()
+        } else {
+            1
+        };
+        let texture = Self::generate(gl, gl::TEXTURE_CUBE_MAP);
+
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_CUBE_MAP, texture.id);
+            gl.TexStorage2D(
+                gl::TEXTURE_CUBE_MAP,
+                levels as gl::types::GLsizei,
+                internal_format,
+                size as gl::types::GLsizei,
+                size as gl::types::GLsizei,
+            );
+            gl.TexParameteri(
+                gl::TEXTURE_CUBE_MAP,
+                gl::TEXTURE_MIN_FILTER,
+                if mipmaps {
+                    gl::LINEAR_MIPMAP_LINEAR
+                } else {
+                    gl::LINEAR
+                } as i32,
+            );
+            gl.TexParameteri(
+                gl::TEXTURE_CUBE_MAP,
+                gl::TEXTURE_MAG_FILTER,
+                gl::LINEAR as i32,
+            );
+
+            for &wrap in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl.TexParameteri(gl::TEXTURE_CUBE_MAP, wrap, gl::CLAMP_TO_EDGE as i32);
+            }
+
+            gl.BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+
+        texture
+    }
+
+    /**
+        ARRAYS AND VOLUMES
+    **/
+
+    // Layers of a TEXTURE_2D_ARRAY (e.g. block textures) or slices of a TEXTURE_3D,
+    // first file at layer 0. Mipmaps are generated per layer for arrays
+    pub fn layered_from_files<P>(
+        gl: &gl::GlPtr,
+        target: gl::types::GLenum,
+        paths: &[P],
+        options: &TextureOptions,
+    ) -> Result<Texture, TextureError>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let limit = match target {
+            gl::TEXTURE_2D_ARRAY => gl::MAX_ARRAY_TEXTURE_LAYERS,
+            gl::TEXTURE_3D => gl::MAX_3D_TEXTURE_SIZE,
+            _ => {
+                return Err(TextureError::InvalidSize {
+                    path: String::new(),
+                    reason: format!("target {:#x} has no layers", target),
                 })
             }
-            LoadResult::ImageF32(_) => None,
+        };
+
+        let mut max_layers = 0;
+        unsafe {
+            gl.GetIntegerv(limit, &mut max_layers);
+        }
+
+        check_layer_count(paths.len(), max_layers.max(0) as u32)?;
+
+        let mut layers: Vec<Image> = vec![];
+
+        for path in paths {
+            let image = Image::load_with(path, options)?;
+
+            if let Some(first) = layers.first() {
+                check_layer(path.as_ref(), first, &image)?;
+            }
+
+            layers.push(image);
+        }
+
+        let first = &layers[0];
+        let (internal, format, kind) = first.gl_formats(options.color_space);
+        let texture = Self::generate(gl, target);
+
+        unsafe {
+            gl.BindTexture(target, texture.id);
+            gl.TexImage3D(
+                target,
+                0,
+                internal as i32,
+                first.width as i32,
+                first.height as i32,
+                layers.len() as i32,
+                0,
+                format,
+                kind,
+                std::ptr::null(),
+            );
         }
+
+        for (layer, image) in layers.iter().enumerate() {
+            upload_layer(gl, target, layer as u32, image, options);
+        }
+
+        apply_options(gl, target, first.channels, options);
+
+        unsafe {
+            gl.BindTexture(target, 0);
+        }
+
+        Ok(texture)
     }
 
+    pub fn generate_mipmaps(&self) {
+        unsafe {
+            self.gl.BindTexture(self.target, self.id);
+            self.gl.GenerateMipmap(self.target);
+            self.gl.BindTexture(self.target, 0);
+        }
+    }
+
+    // Sampling state comes from a sampler::Sampler bound to the same unit
     pub fn bind(&self) {
         // todo multisampled?
 
         unsafe {
-            self.gl.BindTexture(gl::TEXTURE_2D, self.id);
+            self.gl.BindTexture(self.target, self.id);
+        }
+    }
+}
 
-            self.gl.TexParameterf(
-                gl::TEXTURE_2D,
-                gl::TEXTURE_MAX_ANISOTROPY_EXT,
-                MAX_TEXTURE_FILTERING,
-            );
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+// Level 0 of `target`, a 2D texture or a cubemap face, has to be bound
+fn upload(gl: &gl::GlPtr, target: gl::types::GLenum, image: &Image, options: &TextureOptions) {
+    let (internal, format, kind) = image.gl_formats(options.color_space);
+
+    unsafe {
+        // the font loader leaves 1, other uploads may leave anything
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, image.row_alignment());
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+
+        gl.TexImage2D(
+            target,
+            0,
+            internal as i32,
+            image.width as i32,
+            image.height as i32,
+            0,
+            format,
+            kind,
+            image.pixels(),
+        );
+
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    }
+}
+
+fn check_layer_count(count: usize, max_layers: u32) -> Result<(), TextureError> {
+    if count == 0 || count > max_layers as usize {
+        return Err(TextureError::InvalidSize {
+            path: String::new(),
+            reason: format!("{} layers, expected 1 to {}", count, max_layers),
+        });
+    }
+
+    Ok(())
+}
+
+// Every layer has the size and channels of the first one
+fn check_layer(path: &std::path::Path, first: &Image, image: &Image) -> Result<(), TextureError> {
+    if (image.width, image.height, image.channels) != (first.width, first.height, first.channels) {
+        return Err(TextureError::InvalidSize {
+            path: path.to_string_lossy().into_owned(),
+            reason: format!(
+                "layer is {}x{} with {} channels, expected {}x{} with {}",
+                image.width,
+                image.height,
+                image.channels,
+                first.width,
+                first.height,
+                first.channels
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+// Level 0 of one layer of an array or volume bound to `target`
+fn upload_layer(
+    gl: &gl::GlPtr,
+    target: gl::types::GLenum,
+    layer: u32,
+    image: &Image,
+    options: &TextureOptions,
+) {
+    let (_, format, kind) = image.gl_formats(options.color_space);
+
+    unsafe {
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, image.row_alignment());
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+
+        gl.TexSubImage3D(
+            target,
+            0,
+            0,
+            0,
+            layer as i32,
+            image.width as i32,
+            image.height as i32,
+            1,
+            format,
+            kind,
+            image.pixels(),
+        );
+
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    }
+}
+
+// Texture has to be bound to `target`, generates mipmaps if asked
+fn apply_options(
+    gl: &gl::GlPtr,
+    target: gl::types::GLenum,
+    channels: u32,
+    options: &TextureOptions,
+) {
+    unsafe {
+        if let Some(swizzle) = swizzle(channels) {
+            gl.TexParameteriv(target, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
+        }
+
+        let wrap = options.wrap as i32;
+        gl.TexParameteri(target, gl::TEXTURE_WRAP_S, wrap);
+        gl.TexParameteri(target, gl::TEXTURE_WRAP_T, wrap);
+        gl.TexParameteri(target, gl::TEXTURE_WRAP_R, wrap);
+        gl.TexParameteri(target, gl::TEXTURE_MIN_FILTER, options.min_filter() as i32);
+        gl.TexParameteri(target, gl::TEXTURE_MAG_FILTER, options.mag_filter() as i32);
+        gl.TexParameterf(
+            target,
+            gl::TEXTURE_MAX_ANISOTROPY_EXT,
+            options
+                .anisotropy
+                .unwrap_or(MAX_TEXTURE_FILTERING)
+                .min(MAX_TEXTURE_FILTERING)
+                .max(1.),
+        );
+
+        if options.mipmaps {
+            gl.GenerateMipmap(target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(channels: u32, data: PixelData) -> Image {
+        Image {
+            width: 1,
+            height: 1,
+            channels,
+            data,
         }
     }
+
+    #[test]
+    fn float_images_keep_full_precision() {
+        let expected = [gl::R32F, gl::RG32F, gl::RGB32F, gl::RGBA32F];
+
+        for (channels, &internal) in (1..=4).zip(&expected) {
+            let float = image(channels, PixelData::F32(vec![0.; channels as usize]));
+
+            for &color_space in &[ColorSpace::Srgb, ColorSpace::Linear] {
+                let (format, _, kind) = float.gl_formats(color_space);
+                assert_eq!((format, kind), (internal, gl::FLOAT));
+            }
+        }
+    }
+
+    fn write_png(name: &str, depth: png::BitDepth, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.png", name, std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+
+        let mut encoder = png::Encoder::new(file, 2, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(depth);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(bytes)
+            .unwrap();
+
+        path
+    }
+
+    #[test]
+    fn loads_16_bit_png_without_losing_precision() {
+        let path = write_png(
+            "texture_16",
+            png::BitDepth::Sixteen,
+            &[0x12, 0x34, 0xff, 0xfe],
+        );
+        let image = Image::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((image.width, image.height, image.channels), (2, 1, 1));
+        match image.data {
+            PixelData::U16(data) => assert_eq!(data, vec![0x1234, 0xfffe]),
+            _ => panic!("expected 16-bit pixels"),
+        }
+    }
+
+    #[test]
+    fn loads_8_bit_png_through_stb_image() {
+        let path = write_png("texture_8", png::BitDepth::Eight, &[7, 200]);
+        let image = Image::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        match image.data {
+            PixelData::U8(data) => assert_eq!(data, vec![7, 200]),
+            _ => panic!("expected 8-bit pixels"),
+        }
+    }
+
+    #[test]
+    fn layers_match_the_first_one() {
+        let path = std::path::Path::new("layer.png");
+        let first = image(3, PixelData::U8(vec![0; 3]));
+
+        assert!(check_layer(path, &first, &image(3, PixelData::U8(vec![0; 3]))).is_ok());
+
+        let wider = Image {
+            width: 2,
+            ..image(3, PixelData::U8(vec![0; 6]))
+        };
+        let rgba = image(4, PixelData::U8(vec![0; 4]));
+
+        for layer in &[wider, rgba] {
+            match check_layer(path, &first, layer) {
+                Err(TextureError::InvalidSize { path, .. }) => assert_eq!(path, "layer.png"),
+                _ => panic!("mismatched layer accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn layer_count_is_bounded() {
+        assert!(check_layer_count(1, 256).is_ok());
+        assert!(check_layer_count(256, 256).is_ok());
+        assert!(check_layer_count(0, 256).is_err());
+        assert!(check_layer_count(257, 256).is_err());
+        assert!(check_layer_count(1, 0).is_err());
+    }
+
+    #[test]
+    fn srgb_only_applies_to_8_bit_color() {
+        let rgb = image(3, PixelData::U8(vec![0; 3]));
+        assert_eq!(rgb.gl_formats(ColorSpace::Srgb).0, gl::SRGB8);
+        assert_eq!(rgb.gl_formats(ColorSpace::Linear).0, gl::RGB8);
+
+        let rgba16 = image(4, PixelData::U16(vec![0; 4]));
+        assert_eq!(rgba16.gl_formats(ColorSpace::Srgb).0, gl::RGBA16);
+    }
 }
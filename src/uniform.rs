@@ -0,0 +1,228 @@
+use gl;
+use gl::types::{GLenum, GLint};
+
+/**
+    Value that can be uploaded to a GLSL uniform. Arrays are uploaded from
+    slices, `accepts` is used to warn when the shader declares another type.
+**/
+pub trait Uniform: Sized {
+    fn accepts(kind: GLenum) -> bool;
+    fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]);
+}
+
+// Texture unit for sampler uniforms
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sampler(pub i32);
+
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+    pub location: GLint, // -1 for names not in the program
+    pub kind: GLenum,
+    pub size: GLint, // array length, 1 otherwise
+}
+
+// Texture target a sampler type reads from, None for buffers and non-samplers
+pub fn sampler_target(kind: GLenum) -> Option<GLenum> {
+    match kind {
+        gl::SAMPLER_1D | gl::SAMPLER_1D_SHADOW => Some(gl::TEXTURE_1D),
+        gl::SAMPLER_2D
+        | gl::SAMPLER_2D_SHADOW
+        | gl::INT_SAMPLER_2D
+        | gl::UNSIGNED_INT_SAMPLER_2D => Some(gl::TEXTURE_2D),
+        gl::SAMPLER_3D | gl::INT_SAMPLER_3D | gl::UNSIGNED_INT_SAMPLER_3D => Some(gl::TEXTURE_3D),
+        gl::SAMPLER_CUBE
+        | gl::SAMPLER_CUBE_SHADOW
+        | gl::INT_SAMPLER_CUBE
+        | gl::UNSIGNED_INT_SAMPLER_CUBE => Some(gl::TEXTURE_CUBE_MAP),
+        gl::SAMPLER_1D_ARRAY => Some(gl::TEXTURE_1D_ARRAY),
+        gl::SAMPLER_2D_ARRAY
+        | gl::SAMPLER_2D_ARRAY_SHADOW
+        | gl::INT_SAMPLER_2D_ARRAY
+        | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY => Some(gl::TEXTURE_2D_ARRAY),
+        gl::SAMPLER_CUBE_MAP_ARRAY => Some(gl::TEXTURE_CUBE_MAP_ARRAY),
+        gl::SAMPLER_2D_MULTISAMPLE => Some(gl::TEXTURE_2D_MULTISAMPLE),
+        gl::SAMPLER_2D_MULTISAMPLE_ARRAY => Some(gl::TEXTURE_2D_MULTISAMPLE_ARRAY),
+        gl::SAMPLER_2D_RECT => Some(gl::TEXTURE_RECTANGLE),
+        _ => None,
+    }
+}
+
+pub fn is_sampler(kind: GLenum) -> bool {
+    match kind {
+        gl::SAMPLER_1D
+        | gl::SAMPLER_2D
+        | gl::SAMPLER_3D
+        | gl::SAMPLER_CUBE
+        | gl::SAMPLER_1D_SHADOW
+        | gl::SAMPLER_2D_SHADOW
+        | gl::SAMPLER_1D_ARRAY
+        | gl::SAMPLER_2D_ARRAY
+        | gl::SAMPLER_CUBE_MAP_ARRAY
+        | gl::SAMPLER_2D_ARRAY_SHADOW
+        | gl::SAMPLER_CUBE_SHADOW
+        | gl::SAMPLER_2D_MULTISAMPLE
+        | gl::SAMPLER_2D_MULTISAMPLE_ARRAY
+        | gl::SAMPLER_BUFFER
+        | gl::SAMPLER_2D_RECT
+        | gl::INT_SAMPLER_2D
+        | gl::INT_SAMPLER_3D
+        | gl::INT_SAMPLER_CUBE
+        | gl::INT_SAMPLER_2D_ARRAY
+        | gl::UNSIGNED_INT_SAMPLER_2D
+        | gl::UNSIGNED_INT_SAMPLER_3D
+        | gl::UNSIGNED_INT_SAMPLER_CUBE
+        | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY
+        | gl::IMAGE_2D
+        | gl::IMAGE_3D
+        | gl::IMAGE_CUBE
+        | gl::IMAGE_2D_ARRAY => true,
+        _ => false,
+    }
+}
+
+// GLSL name of a uniform type, for warnings
+pub fn type_name(kind: GLenum) -> &'static str {
+    match kind {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::INT => "int",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT => "uint",
+        gl::UNSIGNED_INT_VEC2 => "uvec2",
+        gl::UNSIGNED_INT_VEC3 => "uvec3",
+        gl::UNSIGNED_INT_VEC4 => "uvec4",
+        gl::BOOL => "bool",
+        gl::BOOL_VEC2 => "bvec2",
+        gl::BOOL_VEC3 => "bvec3",
+        gl::BOOL_VEC4 => "bvec4",
+        gl::FLOAT_MAT2 => "mat2",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::FLOAT_MAT2x3 => "mat2x3",
+        gl::FLOAT_MAT2x4 => "mat2x4",
+        gl::FLOAT_MAT3x2 => "mat3x2",
+        gl::FLOAT_MAT3x4 => "mat3x4",
+        gl::FLOAT_MAT4x2 => "mat4x2",
+        gl::FLOAT_MAT4x3 => "mat4x3",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_3D => "sampler3D",
+        gl::SAMPLER_CUBE => "samplerCube",
+        gl::SAMPLER_2D_ARRAY => "sampler2DArray",
+        gl::SAMPLER_2D_SHADOW => "sampler2DShadow",
+        gl::SAMPLER_2D_MULTISAMPLE => "sampler2DMS",
+        kind if is_sampler(kind) => "sampler",
+        _ => "unknown",
+    }
+}
+
+/**
+    IMPLEMENTATIONS
+**/
+
+// scalars and vectors, `$call` takes (location, count, pointer)
+macro_rules! impl_uniform {
+    ($type:ty, $scalar:ty, $call:ident, [$($kind:path),+]) => {
+        impl Uniform for $type {
+            fn accepts(kind: GLenum) -> bool {
+                match kind {
+                    $($kind)|+ => true,
+                    _ => false,
+                }
+            }
+
+            fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]) {
+                unsafe {
+                    gl.$call(location, values.len() as i32, values.as_ptr() as *const $scalar);
+                }
+            }
+        }
+    };
+}
+
+// matrices are column major in both glm and GLSL
+macro_rules! impl_uniform_matrix {
+    ($type:ty, $call:ident, $kind:path) => {
+        impl Uniform for $type {
+            fn accepts(kind: GLenum) -> bool {
+                kind == $kind
+            }
+
+            fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]) {
+                unsafe {
+                    gl.$call(
+                        location,
+                        values.len() as i32,
+                        gl::FALSE,
+                        values.as_ptr() as *const f32,
+                    );
+                }
+            }
+        }
+    };
+}
+
+impl_uniform!(f32, f32, Uniform1fv, [gl::FLOAT]);
+impl_uniform!(glm::Vec2, f32, Uniform2fv, [gl::FLOAT_VEC2]);
+impl_uniform!(glm::Vec3, f32, Uniform3fv, [gl::FLOAT_VEC3]);
+impl_uniform!(glm::Vec4, f32, Uniform4fv, [gl::FLOAT_VEC4]);
+
+// int vectors also set bool vectors, like glUniform*i does
+impl_uniform!(glm::I32Vec2, i32, Uniform2iv, [gl::INT_VEC2, gl::BOOL_VEC2]);
+impl_uniform!(glm::I32Vec3, i32, Uniform3iv, [gl::INT_VEC3, gl::BOOL_VEC3]);
+impl_uniform!(glm::I32Vec4, i32, Uniform4iv, [gl::INT_VEC4, gl::BOOL_VEC4]);
+
+impl_uniform!(u32, u32, Uniform1uiv, [gl::UNSIGNED_INT]);
+impl_uniform!(glm::U32Vec2, u32, Uniform2uiv, [gl::UNSIGNED_INT_VEC2]);
+impl_uniform!(glm::U32Vec3, u32, Uniform3uiv, [gl::UNSIGNED_INT_VEC3]);
+impl_uniform!(glm::U32Vec4, u32, Uniform4uiv, [gl::UNSIGNED_INT_VEC4]);
+
+impl_uniform_matrix!(glm::Mat2, UniformMatrix2fv, gl::FLOAT_MAT2);
+impl_uniform_matrix!(glm::Mat3, UniformMatrix3fv, gl::FLOAT_MAT3);
+impl_uniform_matrix!(glm::Mat4, UniformMatrix4fv, gl::FLOAT_MAT4);
+
+// glm names are rows x columns, GLSL names are columns x rows
+impl_uniform_matrix!(glm::Mat3x2, UniformMatrix2x3fv, gl::FLOAT_MAT2x3);
+impl_uniform_matrix!(glm::Mat4x2, UniformMatrix2x4fv, gl::FLOAT_MAT2x4);
+impl_uniform_matrix!(glm::Mat2x3, UniformMatrix3x2fv, gl::FLOAT_MAT3x2);
+impl_uniform_matrix!(glm::Mat4x3, UniformMatrix3x4fv, gl::FLOAT_MAT3x4);
+impl_uniform_matrix!(glm::Mat2x4, UniformMatrix4x2fv, gl::FLOAT_MAT4x2);
+impl_uniform_matrix!(glm::Mat3x4, UniformMatrix4x3fv, gl::FLOAT_MAT4x3);
+
+// ints also set samplers and bools, like glUniform1i does
+impl Uniform for i32 {
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::INT || kind == gl::BOOL || is_sampler(kind)
+    }
+
+    fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]) {
+        unsafe {
+            gl.Uniform1iv(location, values.len() as i32, values.as_ptr());
+        }
+    }
+}
+
+impl Uniform for bool {
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::BOOL
+    }
+
+    fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]) {
+        let values: Vec<i32> = values.iter().map(|&value| value as i32).collect();
+        i32::upload(gl, location, &values);
+    }
+}
+
+impl Uniform for Sampler {
+    fn accepts(kind: GLenum) -> bool {
+        is_sampler(kind)
+    }
+
+    fn upload(gl: &gl::GlPtr, location: GLint, values: &[Self]) {
+        let units: Vec<i32> = values.iter().map(|sampler| sampler.0).collect();
+        i32::upload(gl, location, &units);
+    }
+}
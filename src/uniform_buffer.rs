@@ -0,0 +1,166 @@
+use crate::camera::Camera;
+use crate::shader::Program;
+use gl;
+use std::marker::PhantomData;
+
+// Binding points shared with `layout (std140, binding = N)` in shaders
+pub const FRAME_BINDING: u32 = 0;
+
+/**
+    STD140 LAYOUT
+    Fields of `#[repr(C)]` blocks have to use these instead of glm types,
+    vec3 and matrix columns are aligned to 16 bytes in std140.
+**/
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Vec3Std140 {
+    pub xyz: [f32; 3],
+    _padding: f32,
+}
+
+impl From<&glm::Vec3> for Vec3Std140 {
+    fn from(v: &glm::Vec3) -> Self {
+        Self {
+            xyz: [v.x, v.y, v.z],
+            _padding: 0.,
+        }
+    }
+}
+
+// Column major like glm, every column is a vec4 so no padding is needed
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Mat4Std140(pub [f32; 16]);
+
+impl From<&glm::Mat4> for Mat4Std140 {
+    fn from(m: &glm::Mat4) -> Self {
+        let mut values = [0.; 16];
+        values.copy_from_slice(m.as_slice());
+        Self(values)
+    }
+}
+
+/**
+    BUFFER
+**/
+
+pub struct UniformBuffer<T: Copy> {
+    gl: gl::GlPtr,
+    id: gl::types::GLuint,
+    binding: u32,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffer<T> {
+    // Allocated and bound to `binding` for good
+    pub fn new(gl: &gl::GlPtr, binding: u32, data: &T) -> Self {
+        let mut id = 0;
+
+        unsafe {
+            gl.GenBuffers(1, &mut id);
+            gl.BindBuffer(gl::UNIFORM_BUFFER, id);
+            gl.BufferData(
+                gl::UNIFORM_BUFFER,
+                std::mem::size_of::<T>() as gl::types::GLsizeiptr,
+                data as *const T as *const gl::types::GLvoid,
+                gl::DYNAMIC_DRAW,
+            );
+            gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl.BindBufferBase(gl::UNIFORM_BUFFER, binding, id);
+        }
+
+        Self {
+            gl: gl.clone(),
+            id,
+            binding,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn update(&self, data: &T) {
+        unsafe {
+            self.gl.BindBuffer(gl::UNIFORM_BUFFER, self.id);
+            self.gl.BufferSubData(
+                gl::UNIFORM_BUFFER,
+                0,
+                std::mem::size_of::<T>() as gl::types::GLsizeiptr,
+                data as *const T as *const gl::types::GLvoid,
+            );
+            self.gl.BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+    }
+
+    pub fn binding(&self) -> u32 {
+        self.binding
+    }
+
+    // Block in the program has to be the same size and at the same binding as `T`
+    pub fn check_layout(&self, program: &Program, block: &str) -> Result<(), String> {
+        let active = program
+            .reflection()
+            .uniform_block(block)
+            .ok_or_else(|| format!("uniform block {} is not used", block))?;
+
+        if active.data_size as usize != std::mem::size_of::<T>() {
+            return Err(format!(
+                "uniform block {} is {} bytes, buffer is {}",
+                block,
+                active.data_size,
+                std::mem::size_of::<T>()
+            ));
+        }
+
+        if active.binding as u32 != self.binding {
+            return Err(format!(
+                "uniform block {} is at binding {}, buffer at {}",
+                block, active.binding, self.binding
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Copy> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/**
+    PER FRAME DATA
+**/
+
+// `Light` in shaders/common/light.glsl
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct LightStd140 {
+    pub position: Vec3Std140,
+    pub ambient: Vec3Std140,
+    pub diffuse: Vec3Std140,
+    pub specular: Vec3Std140,
+}
+
+// `Frame` block in shaders/common/frame.glsl
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct FrameData {
+    pub projection: Mat4Std140,
+    pub view: Mat4Std140,
+    pub view_position: Vec3Std140,
+    pub light: LightStd140,
+}
+
+impl FrameData {
+    pub fn new(camera: &Camera, light: LightStd140) -> Self {
+        Self {
+            projection: (&camera.projection).into(),
+            view: (&camera.view).into(),
+            view_position: (&camera.position).into(),
+            light,
+        }
+    }
+}
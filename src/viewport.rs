@@ -0,0 +1,191 @@
+use crate::camera::{Camera, EditorView, Projection};
+
+// Rectangle in drawable pixels, origin at bottom left like glViewport
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width: width.max(1),
+            height: height.max(1),
+        }
+    }
+
+    pub fn full(width: u32, height: u32) -> Self {
+        Self::new(0, 0, width, height)
+    }
+
+    pub fn apply(&self, gl: &gl::GlPtr) {
+        unsafe {
+            gl.Viewport(self.x, self.y, self.width as i32, self.height as i32);
+        }
+    }
+
+    // Clear only inside the viewport, leaves scissor test enabled
+    pub fn clear(&self, gl: &gl::GlPtr, color: &glm::Vec4, depth: f64) {
+        unsafe {
+            gl.Enable(gl::SCISSOR_TEST);
+            gl.Scissor(self.x, self.y, self.width as i32, self.height as i32);
+            gl.ClearColor(color.x, color.y, color.z, color.w);
+            gl.ClearDepth(depth);
+            gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+    }
+}
+
+/**
+    LAYOUTS
+**/
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ViewLayout {
+    Single,
+    Split,            // main camera left, second camera right
+    PictureInPicture, // top-down minimap in the corner
+    Quad,             // main camera and top, front, side editor views
+}
+
+impl ViewLayout {
+    pub fn next(self) -> Self {
+        match self {
+            ViewLayout::Single => ViewLayout::Split,
+            ViewLayout::Split => ViewLayout::PictureInPicture,
+            ViewLayout::PictureInPicture => ViewLayout::Quad,
+            ViewLayout::Quad => ViewLayout::Single,
+        }
+    }
+
+    // First viewport always belongs to the main camera
+    pub fn viewports(self, width: u32, height: u32) -> Vec<Viewport> {
+        let half_width = width / 2;
+        let half_height = height / 2;
+
+        match self {
+            ViewLayout::Single => vec![Viewport::full(width, height)],
+            ViewLayout::Split => vec![
+                Viewport::new(0, 0, half_width, height),
+                Viewport::new(half_width as i32, 0, width - half_width, height),
+            ],
+            ViewLayout::PictureInPicture => {
+                // narrow or minimized windows shrink the inset instead of underflowing
+                let margin = 10;
+                let size = (height / 3).min(width.saturating_sub(2 * margin));
+
+                vec![
+                    Viewport::full(width, height),
+                    Viewport::new(
+                        width.saturating_sub(size + margin) as i32,
+                        height.saturating_sub(size + margin) as i32,
+                        size,
+                        size,
+                    ),
+                ]
+            }
+            ViewLayout::Quad => vec![
+                Viewport::new(0, half_height as i32, half_width, height - half_height),
+                Viewport::new(
+                    half_width as i32,
+                    half_height as i32,
+                    width - half_width,
+                    height - half_height,
+                ),
+                Viewport::new(0, 0, half_width, half_height),
+                Viewport::new(half_width as i32, 0, width - half_width, half_height),
+            ],
+        }
+    }
+}
+
+pub struct View {
+    pub camera: Camera,
+    pub label: &'static str,
+}
+
+fn editor_camera(main: &Camera, view: EditorView, target: &glm::Vec3, half_height: f32) -> Camera {
+    let mut camera = main.clone();
+    camera.set_projection(Projection::Orthographic { half_height });
+    camera.set_editor_view(view, target, 20.);
+    camera
+}
+
+// Cameras for every viewport of the layout, main camera comes first
+pub fn build_views(
+    layout: ViewLayout,
+    main: &Camera,
+    second: &Camera,
+    width: u32,
+    height: u32,
+) -> Vec<View> {
+    let origin = glm::vec3(0., 0., 0.);
+
+    let cameras = match layout {
+        ViewLayout::Single => vec![(main.clone(), "")],
+        ViewLayout::Split => vec![(main.clone(), "PLAYER 1"), (second.clone(), "PLAYER 2")],
+        ViewLayout::PictureInPicture => {
+            // minimap follows main camera from above
+            let above = glm::vec3(main.position.x, 0., main.position.z);
+
+            vec![
+                (main.clone(), ""),
+                (editor_camera(main, EditorView::Top, &above, 6.), "MAP"),
+            ]
+        }
+        ViewLayout::Quad => vec![
+            (main.clone(), "PERSPECTIVE"),
+            (editor_camera(main, EditorView::Top, &origin, 5.), "TOP"),
+            (editor_camera(main, EditorView::Front, &origin, 5.), "FRONT"),
+            (editor_camera(main, EditorView::Side, &origin, 5.), "SIDE"),
+        ],
+    };
+
+    cameras
+        .into_iter()
+        .zip(layout.viewports(width, height))
+        .map(|((mut camera, label), viewport)| {
+            camera.set_viewport(&viewport, height);
+            View { camera, label }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_fit_any_window() {
+        let layouts = [
+            ViewLayout::Single,
+            ViewLayout::Split,
+            ViewLayout::PictureInPicture,
+            ViewLayout::Quad,
+        ];
+
+        for &layout in &layouts {
+            for &(width, height) in &[(1024, 600), (15, 600), (1, 1), (0, 0)] {
+                for viewport in layout.viewports(width, height) {
+                    assert!(viewport.x >= 0 && viewport.y >= 0);
+                    assert!(viewport.x as u32 + viewport.width <= width.max(1));
+                    assert!(viewport.y as u32 + viewport.height <= height.max(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn picture_in_picture_is_top_right() {
+        let inset = ViewLayout::PictureInPicture.viewports(1024, 600)[1];
+        assert_eq!(
+            (inset.x, inset.y, inset.width, inset.height),
+            (814, 390, 200, 200)
+        );
+    }
+}